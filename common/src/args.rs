@@ -0,0 +1,75 @@
+use std::collections::BTreeMap;
+
+/// A tiny command-line parser. Flags look like `--key=value` or just `--key`; everything else is a
+/// free argument. Call `done` after asking for everything, so unknown flags aren't silently
+/// ignored.
+pub struct CmdArgs {
+    kv: BTreeMap<String, Option<String>>,
+    free: Vec<String>,
+}
+
+impl CmdArgs {
+    // Not Default, since this reads the real arguments
+    #[allow(clippy::new_without_default)]
+    pub fn new() -> Self {
+        let mut args = Self {
+            kv: BTreeMap::new(),
+            free: Vec::new(),
+        };
+        for arg in std::env::args().skip(1) {
+            if let Some(flag) = arg.strip_prefix("--") {
+                match flag.split_once('=') {
+                    Some((key, value)) => {
+                        args.kv.insert(format!("--{key}"), Some(value.to_string()));
+                    }
+                    None => {
+                        args.kv.insert(arg, None);
+                    }
+                }
+            } else {
+                args.free.push(arg);
+            }
+        }
+        args
+    }
+
+    pub fn required_free(&mut self) -> String {
+        if self.free.is_empty() {
+            panic!("Pass a path to a .geojson containing some polygons");
+        }
+        self.free.remove(0)
+    }
+
+    pub fn optional_free(&mut self) -> Option<String> {
+        if self.free.is_empty() {
+            None
+        } else {
+            Some(self.free.remove(0))
+        }
+    }
+
+    pub fn optional(&mut self, key: &str) -> Option<String> {
+        match self.kv.remove(key) {
+            Some(Some(value)) => Some(value),
+            Some(None) => panic!("{key} needs a value, like {key}=..."),
+            None => None,
+        }
+    }
+
+    pub fn optional_parse<T: std::str::FromStr>(&mut self, key: &str) -> Option<T> {
+        let value = self.optional(key)?;
+        match value.parse() {
+            Ok(x) => Some(x),
+            Err(_) => panic!("Couldn't parse {key}={value}"),
+        }
+    }
+
+    pub fn done(self) {
+        if !self.free.is_empty() {
+            panic!("Unused arguments: {:?}", self.free);
+        }
+        if !self.kv.is_empty() {
+            panic!("Unused flags: {:?}", self.kv.keys().collect::<Vec<_>>());
+        }
+    }
+}
@@ -0,0 +1,258 @@
+use std::time::{Duration, Instant};
+
+use anyhow::{bail, Result};
+use bevy::prelude::{
+    Camera, Commands, Component, DespawnRecursiveExt, Entity, Input, KeyCode, Parent, Query, Res,
+    ResMut, Resource, Time, With, Without,
+};
+use bevy::time::TimeUpdateStrategy;
+use bevy_egui::egui;
+use bevy_image_export::ImageExportCamera;
+
+use crate::args::CmdArgs;
+
+/// Saves rendered frames as PNGs. While recording, time advances by a fixed step every frame, so
+/// the result doesn't depend on how fast frames really render.
+///
+/// Each app spawns its own `CaptureCamera`s once recording starts, with `export` on them.
+#[derive(Resource)]
+pub struct Capture {
+    /// Every recording goes in a new numbered directory inside this one
+    output_dir: String,
+    /// Simulated time between rendered frames
+    step: Duration,
+    /// Only save every Nth rendered frame
+    pub every: u32,
+    /// Where the current or last recording went
+    take_dir: Option<&'static str>,
+    /// bevy_image_export wants a static string, so every directory recorded to is leaked once
+    /// and kept here to reuse
+    leaked_dirs: Vec<&'static str>,
+    recording: bool,
+    /// Rendered frames since recording started
+    frame: u32,
+    saved: u32,
+    /// The simulated time of the previous frame
+    last_instant: Option<Instant>,
+}
+
+/// Marks a camera rendering into images, a child of the camera whose view it saves
+#[derive(Component)]
+pub struct CaptureCamera;
+
+/// Marks a capture camera drawing into another one's image, instead of saving its own. With a
+/// split screen, each view draws into its part of one image.
+#[derive(Component)]
+pub struct CaptureInto(pub Entity);
+
+impl Capture {
+    pub fn new(output_dir: String, fps: f32, every: u32) -> Self {
+        Self {
+            output_dir,
+            step: Duration::from_secs_f32(1.0 / fps),
+            every: every.max(1),
+            take_dir: None,
+            leaked_dirs: Vec::new(),
+            recording: false,
+            frame: 0,
+            saved: 0,
+            last_instant: None,
+        }
+    }
+
+    /// Reads `--capture=dir`, `--capture-fps`, and `--capture-every`. With `--capture`, recording
+    /// starts right away.
+    pub fn from_args(args: &mut CmdArgs) -> Result<Self> {
+        let capture_dir = args.optional("--capture");
+        let fps: f32 = args.optional_parse("--capture-fps").unwrap_or(30.0);
+        if fps <= 0.0 || !fps.is_finite() {
+            bail!("--capture-fps must be a positive number, not {fps}");
+        }
+        let mut capture = Self::new(
+            capture_dir.clone().unwrap_or_else(|| "frames".to_string()),
+            fps,
+            args.optional_parse("--capture-every").unwrap_or(1),
+        );
+        if capture_dir.is_some() {
+            capture.start();
+        }
+        Ok(capture)
+    }
+
+    pub fn start(&mut self) {
+        // Never mix frames from different recordings
+        let take = (1..)
+            .map(|n| format!("{}/{n:03}", self.output_dir))
+            .find(|dir| !std::path::Path::new(dir).exists())
+            .unwrap();
+        let take_dir = match self.leaked_dirs.iter().find(|dir| **dir == take) {
+            Some(dir) => *dir,
+            None => {
+                let dir: &'static str = Box::leak(take.into_boxed_str());
+                self.leaked_dirs.push(dir);
+                dir
+            }
+        };
+        self.take_dir = Some(take_dir);
+        self.recording = true;
+        self.frame = 0;
+        self.saved = 0;
+        self.last_instant = None;
+    }
+
+    pub fn stop(&mut self) {
+        self.recording = false;
+    }
+
+    /// What to put on capture cameras, while recording
+    pub fn export(&self) -> Option<ImageExportCamera> {
+        match self.take_dir {
+            Some(dir) if self.recording => Some(ImageExportCamera {
+                output_dir: dir,
+                extension: "png",
+            }),
+            _ => None,
+        }
+    }
+}
+
+/// Runs at the end of every frame, deciding whether the next render gets saved
+pub fn capture_frames(
+    mut commands: Commands,
+    mut capture: ResMut<Capture>,
+    mut strategy: ResMut<TimeUpdateStrategy>,
+    time: Res<Time>,
+    mut capture_cameras: Query<(Entity, &mut Camera, Option<&CaptureInto>), With<CaptureCamera>>,
+) {
+    let export = match capture.export() {
+        Some(export) => export,
+        None => {
+            if !capture_cameras.is_empty() {
+                for (entity, ..) in &capture_cameras {
+                    commands.entity(entity).despawn_recursive();
+                }
+                *strategy = TimeUpdateStrategy::Automatic;
+            }
+            return;
+        }
+    };
+
+    // Fix the time of the next frame
+    let last = capture
+        .last_instant
+        .or_else(|| time.last_update())
+        .unwrap_or_else(Instant::now);
+    let next = last + capture.step;
+    capture.last_instant = Some(next);
+    *strategy = TimeUpdateStrategy::ManualInstant(next);
+
+    // The app is still spawning them. bevy_image_export sets them up next frame.
+    if capture_cameras.is_empty() {
+        return;
+    }
+
+    let save = capture.frame.is_multiple_of(capture.every);
+    capture.frame += 1;
+    if save {
+        capture.saved += 1;
+    }
+    for (entity, mut camera, into) in &mut capture_cameras {
+        camera.is_active = save;
+        // Without the component, nothing is saved, not even the last image again
+        if into.is_some() {
+            continue;
+        }
+        if save {
+            commands.entity(entity).insert(export.clone());
+        } else {
+            commands.entity(entity).remove::<ImageExportCamera>();
+        }
+    }
+}
+
+/// Keeps each capture camera's projection and viewport the same as its parent's, like after
+/// zooming or resizing
+pub fn follow_parent<P: Component + Clone>(
+    parents: Query<(&Camera, &P), Without<CaptureCamera>>,
+    mut capture_cameras: Query<(&Parent, &mut Camera, &mut P), With<CaptureCamera>>,
+) {
+    for (parent, mut camera, mut projection) in &mut capture_cameras {
+        if let Ok((parent_camera, parent_projection)) = parents.get(parent.get()) {
+            *projection = parent_projection.clone();
+            let same_viewport = match (&camera.viewport, &parent_camera.viewport) {
+                (Some(a), Some(b)) => {
+                    a.physical_position == b.physical_position && a.physical_size == b.physical_size
+                }
+                (None, None) => true,
+                _ => false,
+            };
+            if !same_viewport {
+                camera.viewport = parent_camera.viewport.clone();
+            }
+        }
+    }
+}
+
+/// Points cameras at the image of the capture camera they draw into, once bevy_image_export has
+/// made it
+pub fn share_images(
+    exporting: Query<&Camera, (With<CaptureCamera>, Without<CaptureInto>)>,
+    mut sharing: Query<(&mut Camera, &CaptureInto)>,
+) {
+    for (mut camera, into) in &mut sharing {
+        if let Ok(target) = exporting.get(into.0) {
+            if camera.target != target.target {
+                camera.target = target.target.clone();
+            }
+        }
+    }
+}
+
+pub fn toggle_key(keyboard: Res<Input<KeyCode>>, mut capture: ResMut<Capture>) {
+    if keyboard.just_pressed(KeyCode::F12) {
+        if capture.recording {
+            capture.stop();
+        } else {
+            capture.start();
+        }
+    }
+}
+
+pub fn controls(ui: &mut egui::Ui, capture: &mut Capture) {
+    ui.separator();
+    ui.horizontal(|ui| {
+        ui.heading("Capture");
+        if capture.recording {
+            if ui.button("Stop (F12)").clicked() {
+                capture.stop();
+            }
+        } else if ui.button("Record (F12)").clicked() {
+            capture.start();
+        }
+    });
+    ui.add(egui::Slider::new(&mut capture.every, 1..=10).text("save every Nth frame"));
+    ui.label(format!(
+        "{:.0} frames per simulated second",
+        1.0 / capture.step.as_secs_f32() / capture.every as f32
+    ));
+    if let Some(dir) = capture.take_dir {
+        ui.label(format!("Saved {} frames to {dir}/", capture.saved));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recording_again_reuses_the_directory() {
+        let mut capture = Capture::new("does-not-exist".to_string(), 30.0, 1);
+        for _ in 0..3 {
+            // Nothing was saved, so every recording goes to the same place
+            capture.start();
+            capture.stop();
+        }
+        assert_eq!(capture.take_dir, Some("does-not-exist/001"));
+        assert_eq!(capture.leaked_dirs.len(), 1);
+    }
+}
@@ -0,0 +1,5 @@
+//! Pieces shared by glitchcity and nolli
+
+pub mod args;
+pub mod capture;
+pub mod projection;
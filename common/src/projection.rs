@@ -0,0 +1,73 @@
+use geo::{Coord, HaversineDistance, Point, Rect};
+
+/// Transforms between WGS84 and meters. Y points north.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Projection {
+    /// Distances are measured from here, in WGS84
+    origin: Point,
+    /// Subtracted after measuring, in meters
+    center: Coord,
+}
+
+// Matches what geo's haversine_distance uses
+const EARTH_RADIUS_METERS: f64 = 6371008.8;
+
+impl Projection {
+    /// Meters from the origin
+    pub fn new(origin: Coord) -> Self {
+        Self {
+            origin: origin.into(),
+            center: Coord::zero(),
+        }
+    }
+
+    /// Meters from the center of the bounding box
+    pub fn centered(bbox: Rect) -> Self {
+        let mut projection = Self::new(bbox.min());
+        projection.center = Rect::new(
+            projection.to_meters(bbox.min()),
+            projection.to_meters(bbox.max()),
+        )
+        .center();
+        projection
+    }
+
+    pub fn to_meters(self, c: Coord) -> Coord {
+        // Distances are unsigned, but points from other data can be west or south of the origin
+        let x = Point::new(c.x, self.origin.y()).haversine_distance(&self.origin)
+            * (c.x - self.origin.x()).signum();
+        let y = Point::new(self.origin.x(), c.y).haversine_distance(&self.origin)
+            * (c.y - self.origin.y()).signum();
+        Coord { x, y } - self.center
+    }
+
+    /// The inverse of `to_meters`
+    pub fn to_lonlat(self, c: Coord) -> Coord {
+        let c = c + self.center;
+        let lat1 = self.origin.y().to_radians();
+        let dlon = 2.0 * ((c.x / (2.0 * EARTH_RADIUS_METERS)).sin() / lat1.cos()).asin();
+        let dlat = c.y / EARTH_RADIUS_METERS;
+        Coord {
+            x: self.origin.x() + dlon.to_degrees(),
+            y: self.origin.y() + dlat.to_degrees(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn to_lonlat_inverts_to_meters() {
+        let bbox = Rect::new((-0.13, 51.50), (-0.11, 51.52));
+        for projection in [Projection::new(bbox.min()), Projection::centered(bbox)] {
+            // Including points outside the bounding box, on every side
+            for (x, y) in [(-0.12, 51.51), (-0.2, 51.4), (0.05, 51.6), (-0.13, 51.52)] {
+                let c = Coord { x, y };
+                let back = projection.to_lonlat(projection.to_meters(c));
+                assert!((back.x - c.x).abs() < 1e-9 && (back.y - c.y).abs() < 1e-9);
+            }
+        }
+    }
+}
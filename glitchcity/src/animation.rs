@@ -0,0 +1,392 @@
+use std::time::Duration;
+
+use bevy::ecs::query::ChangeTrackers;
+use bevy::prelude::{
+    Assets, Camera, Color, Component, GlobalTransform, Handle, Input, Local, MouseButton, Quat,
+    Query, Res, ResMut, Resource, StandardMaterial, Transform, Vec3, Windows,
+};
+use bevy_egui::{egui, EguiContext};
+use bevy_tweening::{
+    Animator, AnimatorState, EaseMethod, Lens, RepeatCount, RepeatStrategy, Tween, Tweenable,
+    TweeningDirection,
+};
+use interpolation::{Ease, EaseFunction};
+
+use crate::buildings::VerticalExaggeration;
+use crate::picking;
+use crate::water::WATER_COLOUR;
+
+// Buildings this far apart are a whole animation out of phase in a wave
+const WAVELENGTH_METERS: f32 = 500.0;
+
+#[derive(Resource, Clone, PartialEq)]
+pub struct AnimationSettings {
+    pub height: Track,
+    pub spin: Track,
+    pub colour: Track,
+    pub phase: Phase,
+    /// Where the wave starts, in world space
+    pub wave_origin: Vec3,
+}
+
+#[derive(Clone, PartialEq)]
+pub struct Track {
+    pub playing: bool,
+    pub speed: f32,
+    /// Height is a multiplier, spin is in degrees, colour rotates the hue by degrees
+    pub amplitude: f32,
+    pub easing: Easing,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Phase {
+    Sync,
+    Random,
+    /// Travels outward from a clicked point
+    Wave,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Easing {
+    Linear,
+    Quadratic,
+    Cubic,
+    Sine,
+    Elastic,
+    Bounce,
+}
+
+impl Easing {
+    const ALL: [Easing; 6] = [
+        Easing::Linear,
+        Easing::Quadratic,
+        Easing::Cubic,
+        Easing::Sine,
+        Easing::Elastic,
+        Easing::Bounce,
+    ];
+
+    fn sample(self, x: f32) -> f32 {
+        let function = match self {
+            Easing::Linear => return x,
+            Easing::Quadratic => EaseFunction::QuadraticInOut,
+            Easing::Cubic => EaseFunction::CubicInOut,
+            Easing::Sine => EaseFunction::SineInOut,
+            Easing::Elastic => EaseFunction::ElasticInOut,
+            Easing::Bounce => EaseFunction::BounceInOut,
+        };
+        x.calc(function)
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Easing::Linear => "linear",
+            Easing::Quadratic => "quadratic",
+            Easing::Cubic => "cubic",
+            Easing::Sine => "sine",
+            Easing::Elastic => "elastic",
+            Easing::Bounce => "bounce",
+        }
+    }
+}
+
+impl Default for AnimationSettings {
+    fn default() -> Self {
+        Self {
+            height: Track {
+                playing: true,
+                speed: 1.0,
+                amplitude: 3.0,
+                easing: Easing::Quadratic,
+            },
+            spin: Track {
+                playing: true,
+                speed: 1.0,
+                amplitude: 180.0,
+                easing: Easing::Quadratic,
+            },
+            colour: Track {
+                playing: false,
+                speed: 1.0,
+                amplitude: 60.0,
+                easing: Easing::Sine,
+            },
+            phase: Phase::Sync,
+            wave_origin: Vec3::ZERO,
+        }
+    }
+}
+
+/// Lights up the selected building, whatever its colour
+const SELECTED_GLOW: Color = Color::rgb(0.8, 0.6, 0.0);
+
+#[derive(Component)]
+pub struct BuildingAnimation {
+    /// In world space, in meters
+    pub centroid: Vec3,
+    pub base_colour: Color,
+    /// From 0 to 1
+    pub random_phase: f32,
+    /// From 0 to 1, how much of the building is under water
+    pub submerged: f32,
+    /// Picked by clicking
+    pub selected: bool,
+}
+
+// bevy_tweening linearly drives how far through each animation a building is, from 0 to 1.
+// apply_animations handles easing and amplitude, so those can change without rebuilding tweens.
+// Each track is a separate component, so each has its own Animator to pause independently.
+
+#[derive(Component, Default)]
+pub struct HeightPulse(f32);
+
+#[derive(Component, Default)]
+pub struct Spin(f32);
+
+#[derive(Component, Default)]
+pub struct ColourShift(f32);
+
+pub trait Progress: Component {
+    const DURATION: Duration;
+
+    fn set(&mut self, progress: f32);
+}
+
+impl Progress for HeightPulse {
+    const DURATION: Duration = Duration::from_secs(2);
+
+    fn set(&mut self, progress: f32) {
+        self.0 = progress;
+    }
+}
+
+impl Progress for Spin {
+    const DURATION: Duration = Duration::from_secs(5);
+
+    fn set(&mut self, progress: f32) {
+        self.0 = progress;
+    }
+}
+
+impl Progress for ColourShift {
+    const DURATION: Duration = Duration::from_secs(3);
+
+    fn set(&mut self, progress: f32) {
+        self.0 = progress;
+    }
+}
+
+struct ProgressLens;
+
+impl<T: Progress> Lens<T> for ProgressLens {
+    fn lerp(&mut self, target: &mut T, ratio: f32) {
+        target.set(ratio);
+    }
+}
+
+/// Back and forth forever, starting `phase` of the way through. Since the tween is mirrored, a
+/// phase from 0 to 2 covers a whole cycle.
+pub fn progress_tween<T: Progress>(phase: f32) -> Tween<T> {
+    let phase = phase.rem_euclid(2.0);
+    let (direction, phase) = if phase < 1.0 {
+        (TweeningDirection::Forward, phase)
+    } else {
+        (TweeningDirection::Backward, phase - 1.0)
+    };
+    let mut tween = Tween::new(EaseMethod::Linear, T::DURATION, ProgressLens)
+        .with_repeat_count(RepeatCount::Infinite)
+        .with_repeat_strategy(RepeatStrategy::MirroredRepeat)
+        .with_direction(direction);
+    tween.set_elapsed(T::DURATION.mul_f32(phase));
+    tween
+}
+
+#[allow(clippy::type_complexity)]
+pub fn apply_animations(
+    settings: Res<AnimationSettings>,
+    exaggeration: Res<VerticalExaggeration>,
+    mut query: Query<(
+        &BuildingAnimation,
+        ChangeTrackers<BuildingAnimation>,
+        &HeightPulse,
+        &Spin,
+        &ColourShift,
+        ChangeTrackers<ColourShift>,
+        &mut Transform,
+        &Handle<StandardMaterial>,
+    )>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    for (
+        building,
+        building_changed,
+        height,
+        spin,
+        colour,
+        colour_changed,
+        mut transform,
+        material,
+    ) in &mut query
+    {
+        let height = 1.0 + settings.height.amplitude * settings.height.easing.sample(height.0);
+        transform.scale = Vec3::new(1.0, exaggeration.0 * height, 1.0);
+
+        let spin = settings.spin.amplitude * settings.spin.easing.sample(spin.0);
+        transform.rotation = Quat::from_rotation_y(spin.to_radians());
+
+        // Only touch the material when needed, since that means uploading it again
+        if colour_changed.is_changed() || building_changed.is_changed() || settings.is_changed() {
+            let shift = settings.colour.amplitude * settings.colour.easing.sample(colour.0);
+            if let Color::Hsla {
+                hue,
+                saturation,
+                lightness,
+                alpha,
+            } = building.base_colour.as_hsla()
+            {
+                if let Some(material) = materials.get_mut(material) {
+                    let shifted = Color::hsla(
+                        (hue + shift).rem_euclid(360.0),
+                        saturation,
+                        lightness,
+                        alpha,
+                    );
+                    material.base_color = mix(shifted, WATER_COLOUR, building.submerged);
+                    material.emissive = if building.selected {
+                        SELECTED_GLOW
+                    } else {
+                        Color::BLACK
+                    };
+                }
+            }
+        }
+    }
+}
+
+// t is from 0 to 1
+fn mix(c1: Color, c2: Color, t: f32) -> Color {
+    let [r1, g1, b1, a1] = c1.as_rgba_f32();
+    let [r2, g2, b2, a2] = c2.as_rgba_f32();
+    let lerp = |x: f32, y: f32| x + t * (y - x);
+    Color::rgba(lerp(r1, r2), lerp(g1, g2), lerp(b1, b2), lerp(a1, a2))
+}
+
+pub fn sync_tracks(
+    settings: Res<AnimationSettings>,
+    mut height: Query<&mut Animator<HeightPulse>>,
+    mut spin: Query<&mut Animator<Spin>>,
+    mut colour: Query<&mut Animator<ColourShift>>,
+) {
+    if !settings.is_changed() {
+        return;
+    }
+    for mut animator in &mut height {
+        sync_track(&settings.height, &mut animator);
+    }
+    for mut animator in &mut spin {
+        sync_track(&settings.spin, &mut animator);
+    }
+    for mut animator in &mut colour {
+        sync_track(&settings.colour, &mut animator);
+    }
+}
+
+fn sync_track<T: Progress>(track: &Track, animator: &mut Animator<T>) {
+    // Don't use stop(), which would rewind
+    animator.state = if track.playing {
+        AnimatorState::Playing
+    } else {
+        AnimatorState::Paused
+    };
+    animator.set_speed(track.speed);
+}
+
+/// Restart every animation when the phase changes
+#[allow(clippy::type_complexity)]
+pub fn reset_phases(
+    settings: Res<AnimationSettings>,
+    mut last: Local<Option<(Phase, Vec3)>>,
+    mut query: Query<(
+        &BuildingAnimation,
+        &mut Animator<HeightPulse>,
+        &mut Animator<Spin>,
+        &mut Animator<ColourShift>,
+    )>,
+) {
+    let current = (settings.phase, settings.wave_origin);
+    if *last == Some(current) {
+        return;
+    }
+    *last = Some(current);
+
+    for (building, mut height, mut spin, mut colour) in &mut query {
+        let phase = match settings.phase {
+            Phase::Sync => 0.0,
+            Phase::Random => 2.0 * building.random_phase,
+            // Further buildings lag behind
+            Phase::Wave => {
+                -2.0 * building.centroid.distance(settings.wave_origin) / WAVELENGTH_METERS
+            }
+        };
+        height.set_tweenable(progress_tween::<HeightPulse>(phase));
+        spin.set_tweenable(progress_tween::<Spin>(phase));
+        colour.set_tweenable(progress_tween::<ColourShift>(phase));
+    }
+}
+
+/// In wave mode, clicking the ground starts the wave from there
+pub fn pick_wave_origin(
+    mut ctx: ResMut<EguiContext>,
+    mut settings: ResMut<AnimationSettings>,
+    mouse: Res<Input<MouseButton>>,
+    windows: Res<Windows>,
+    cameras: Query<(&Camera, &GlobalTransform)>,
+) {
+    if settings.phase != Phase::Wave
+        || !mouse.just_pressed(MouseButton::Left)
+        || ctx.ctx_mut().wants_pointer_input()
+    {
+        return;
+    }
+    if let Some(pos) = picking::cursor_ray(&windows, &cameras).and_then(picking::ground_point) {
+        settings.wave_origin = pos;
+    }
+}
+
+pub fn controls(ui: &mut egui::Ui, settings: &mut AnimationSettings) {
+    track_controls(ui, "Height", &mut settings.height, 0.0..=10.0);
+    track_controls(ui, "Spin", &mut settings.spin, 0.0..=360.0);
+    track_controls(ui, "Colour", &mut settings.colour, 0.0..=180.0);
+
+    ui.horizontal(|ui| {
+        ui.label("Phase");
+        ui.radio_value(&mut settings.phase, Phase::Sync, "in sync");
+        ui.radio_value(&mut settings.phase, Phase::Random, "random");
+        ui.radio_value(&mut settings.phase, Phase::Wave, "wave");
+    });
+    if settings.phase == Phase::Wave {
+        ui.label("Click the ground to start the wave there");
+    }
+}
+
+fn track_controls(
+    ui: &mut egui::Ui,
+    name: &str,
+    track: &mut Track,
+    amplitude: std::ops::RangeInclusive<f32>,
+) {
+    ui.separator();
+    ui.horizontal(|ui| {
+        ui.heading(name);
+        ui.checkbox(&mut track.playing, "playing");
+    });
+    ui.add(egui::Slider::new(&mut track.speed, 0.0..=5.0).text("speed"));
+    ui.add(egui::Slider::new(&mut track.amplitude, amplitude).text("amplitude"));
+    egui::ComboBox::from_id_source(name)
+        .selected_text(track.easing.name())
+        .show_ui(ui, |ui| {
+            for easing in Easing::ALL {
+                ui.selectable_value(&mut track.easing, easing, easing.name());
+            }
+        });
+}
@@ -1,26 +1,104 @@
-use bevy::prelude::Vec3;
-use geo::Polygon;
+use bevy::prelude::{Resource, Vec3};
+use geo::orient::{Direction, Orient};
+use geo::{Centroid, MapCoords, Polygon};
+use geojson::JsonObject;
 
-use crate::mesh::MeshBuilder;
+use crate::mesh::{to_world, MeshBuilder};
 
-pub fn extrude(polygon: Polygon, y2: f32, builder: &mut MeshBuilder) {
+#[derive(Resource)]
+pub struct City {
+    pub buildings: Vec<Building>,
+    /// The diagonal of the bounding box, in meters
+    pub extent: f32,
+}
+
+pub struct Building {
+    /// In meters, with the origin at the center of everything. Y points north.
+    pub polygon: Polygon,
+    /// The OSM ID or something else identifying the original feature
+    pub name: String,
+    pub properties: JsonObject,
+    /// In meters
+    pub height: f32,
+    /// The elevation of the floor, in meters. Zero without terrain.
+    pub base: f32,
+}
+
+/// Multiplies the height of every building
+#[derive(Resource, Clone, Copy, PartialEq)]
+pub struct VerticalExaggeration(pub f32);
+
+// Roughly, including the floor
+pub const METERS_PER_LEVEL: f32 = 3.0;
+
+impl Building {
+    /// Uses the OSM height or number of levels, if present
+    pub fn height_from_tags(&self) -> Option<f32> {
+        if let Some(height) = self.properties.get("height").and_then(parse_meters) {
+            return Some(height);
+        }
+        let levels = self
+            .properties
+            .get("building:levels")
+            .and_then(parse_meters)?;
+        Some(levels * METERS_PER_LEVEL)
+    }
+}
+
+// Handles numbers, "12", and "12 m". Zero, negative, and non-finite values mean nothing.
+fn parse_meters(value: &serde_json::Value) -> Option<f32> {
+    let x = match value.as_f64() {
+        Some(x) => x as f32,
+        None => {
+            let text = value.as_str()?.trim().trim_end_matches('m').trim();
+            text.parse::<f32>().ok()?
+        }
+    };
+    (x > 0.0 && x.is_finite()).then_some(x)
+}
+
+/// Extrude the polygon relative to its centroid, returning the centroid. Place the mesh there, so
+/// rotating and scaling happens around the building's own center.
+pub fn extrude_around_centroid(polygon: &Polygon, y2: f32, builder: &mut MeshBuilder) -> Vec3 {
+    let centroid = polygon.centroid().unwrap();
+    let polygon = polygon.map_coords(|c| c - centroid.0);
+    extrude(&polygon, y2, builder);
+    to_world(centroid.0, 0.0)
+}
+
+pub fn extrude(polygon: &Polygon, y2: f32, builder: &mut MeshBuilder) {
     let down = Vec3::NEG_Y;
     let up = Vec3::new(0.0, 1.0, 0.0);
 
     let y1 = 0.0;
 
     // Floor
-    builder.triangulate_polygon(&polygon, y1, down);
+    builder.triangulate_polygon(polygon, y1, down);
 
     // Ceiling
-    builder.triangulate_polygon(&polygon, y2, up);
+    builder.triangulate_polygon(polygon, y2, up);
+
+    add_walls(polygon, y1, y2, builder);
+}
+
+/// Adds a rectangular wall from `y1` to `y2` along every line of the polygon, facing out
+pub fn add_walls(polygon: &Polygon, y1: f32, y2: f32, builder: &mut MeshBuilder) {
+    // With the exterior counter-clockwise and holes clockwise, the outside of the building is
+    // always to the right of each line
+    let polygon = polygon.orient(Direction::Default);
 
     // For every line along the polygon, add a rectangular wall
-    for line in polygon.exterior().lines() {
-        let corner1 = Vec3::new(line.start.x as f32, y1, line.start.y as f32);
-        let corner2 = Vec3::new(line.end.x as f32, y1, line.end.y as f32);
-        let corner3 = Vec3::new(line.end.x as f32, y2, line.end.y as f32);
-        let corner4 = Vec3::new(line.start.x as f32, y2, line.start.y as f32);
+    for line in std::iter::once(polygon.exterior())
+        .chain(polygon.interiors())
+        .flat_map(|ring| ring.lines())
+    {
+        if line.start == line.end {
+            continue;
+        }
+        let corner1 = to_world(line.start, y1);
+        let corner2 = to_world(line.end, y1);
+        let corner3 = to_world(line.end, y2);
+        let corner4 = to_world(line.start, y2);
 
         // Now let's go fetch our buddy Norm
         let bottom_line = corner2 - corner1;
@@ -29,5 +107,30 @@ pub fn extrude(polygon: Polygon, y2: f32, builder: &mut MeshBuilder) {
 
         builder.add_quad([corner1, corner2, corner3, corner4], normal);
     }
-    // TODO Interiors. The normal is reversed
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn parse_meters_accepts_only_positive_heights() {
+        assert_eq!(parse_meters(&json!(12)), Some(12.0));
+        assert_eq!(parse_meters(&json!(7.5)), Some(7.5));
+        assert_eq!(parse_meters(&json!("12")), Some(12.0));
+        assert_eq!(parse_meters(&json!("12 m")), Some(12.0));
+        for bad in [
+            json!(0),
+            json!(-5),
+            json!("0 m"),
+            json!("-5"),
+            json!("inf"),
+            json!("tall"),
+            json!(null),
+        ] {
+            assert_eq!(parse_meters(&bad), None, "{bad}");
+        }
+    }
 }
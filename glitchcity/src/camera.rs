@@ -0,0 +1,247 @@
+use bevy::input::mouse::{MouseMotion, MouseScrollUnit, MouseWheel};
+use bevy::prelude::{
+    default, Assets, Camera, Commands, Entity, EventReader, EventWriter, GlobalTransform, Handle,
+    Input, KeyCode, Local, Mesh, MouseButton, Query, Res, ResMut, Resource, Time, Vec2, Vec3,
+    Windows, With,
+};
+use bevy::render::primitives::Aabb;
+use bevy_egui::{egui, EguiContext};
+use smooth_bevy_cameras::controllers::fps::{ControlEvent as FpsEvent, FpsCameraController};
+use smooth_bevy_cameras::controllers::orbit::{ControlEvent as OrbitEvent, OrbitCameraController};
+use smooth_bevy_cameras::{LookTransform, Smoother};
+
+use crate::buildings::City;
+use crate::colours::BuildingIdx;
+use crate::picking;
+
+// Two clicks closer than this are a double click
+const DOUBLE_CLICK_SECONDS: f64 = 0.3;
+
+#[derive(Resource, Clone, Copy, PartialEq)]
+pub enum CameraMode {
+    /// Fly around with the keyboard
+    Fps,
+    /// Circle around a target with the mouse
+    Orbit,
+}
+
+/// The camera starts with an FPS controller. Switch by swapping the controller component, since
+/// both controllers would fight over the shared smoother.
+pub fn switch_mode(
+    mode: Res<CameraMode>,
+    mut commands: Commands,
+    mut query: Query<(Entity, &mut Smoother), With<LookTransform>>,
+) {
+    if !mode.is_changed() || mode.is_added() {
+        return;
+    }
+    for (entity, mut smoother) in &mut query {
+        let mut entity = commands.entity(entity);
+        match *mode {
+            CameraMode::Fps => {
+                smoother.set_lag_weight(0.0);
+                entity
+                    .remove::<OrbitCameraController>()
+                    .insert(FpsCameraController {
+                        smoothing_weight: 0.0,
+                        ..default()
+                    });
+            }
+            CameraMode::Orbit => {
+                // Smoothing makes focusing on a building glide there
+                smoother.set_lag_weight(0.8);
+                entity
+                    .remove::<FpsCameraController>()
+                    .insert(OrbitCameraController::default());
+            }
+        }
+    }
+}
+
+pub fn fps_controls(
+    mut events: EventWriter<FpsEvent>,
+    keyboard: Res<Input<KeyCode>>,
+    city: Res<City>,
+) {
+    // Cross everything in a few seconds
+    let translate_speed = city.extent / 5.0;
+    let rotate_speed = 2.0;
+
+    if keyboard.pressed(KeyCode::Up) {
+        events.send(FpsEvent::TranslateEye(translate_speed * Vec3::Z));
+    }
+    if keyboard.pressed(KeyCode::Down) {
+        events.send(FpsEvent::TranslateEye(translate_speed * -Vec3::Z));
+    }
+
+    let translate = keyboard.pressed(KeyCode::LShift);
+    for (key, translate_dir, rotate_dir) in [
+        (KeyCode::Left, Vec3::X, -Vec2::X),
+        (KeyCode::Right, -Vec3::X, Vec2::X),
+        (KeyCode::A, -Vec3::Y, Vec2::Y),
+        (KeyCode::Q, Vec3::Y, -Vec2::Y),
+    ] {
+        if keyboard.pressed(key) {
+            if translate {
+                events.send(FpsEvent::TranslateEye(translate_speed * translate_dir));
+            } else {
+                events.send(FpsEvent::Rotate(rotate_speed * rotate_dir));
+            }
+        }
+    }
+}
+
+/// Left drag orbits, right drag pans, and the wheel zooms
+pub fn orbit_controls(
+    mut ctx: ResMut<EguiContext>,
+    mut events: EventWriter<OrbitEvent>,
+    mut motion: EventReader<MouseMotion>,
+    mut wheel: EventReader<MouseWheel>,
+    mouse: Res<Input<MouseButton>>,
+    cameras: Query<(&OrbitCameraController, &LookTransform)>,
+) {
+    let cursor_delta: Vec2 = motion.iter().map(|ev| ev.delta).sum();
+    let wheel: Vec<MouseWheel> = wheel.iter().cloned().collect();
+    if ctx.ctx_mut().wants_pointer_input() {
+        return;
+    }
+    let (controller, look) = if let Some(camera) = cameras.iter().find(|(c, _)| c.enabled) {
+        camera
+    } else {
+        return;
+    };
+
+    if mouse.pressed(MouseButton::Left) {
+        events.send(OrbitEvent::Orbit(
+            controller.mouse_rotate_sensitivity * cursor_delta,
+        ));
+    }
+    if mouse.pressed(MouseButton::Right) {
+        // Pan further when zoomed out
+        events.send(OrbitEvent::TranslateTarget(
+            controller.mouse_translate_sensitivity * look.radius() * cursor_delta,
+        ));
+    }
+
+    let mut scalar = 1.0;
+    for ev in wheel {
+        let lines = match ev.unit {
+            MouseScrollUnit::Line => ev.y,
+            MouseScrollUnit::Pixel => ev.y / controller.pixels_per_line,
+        };
+        scalar *= 1.0 - lines * controller.mouse_wheel_zoom_sensitivity;
+    }
+    if scalar != 1.0 {
+        events.send(OrbitEvent::Zoom(scalar));
+    }
+}
+
+/// Double clicking a building orbits around it
+#[allow(clippy::too_many_arguments)]
+pub fn focus_on_double_click(
+    mode: Res<CameraMode>,
+    time: Res<Time>,
+    mut last_click: Local<Option<f64>>,
+    mut ctx: ResMut<EguiContext>,
+    mouse: Res<Input<MouseButton>>,
+    windows: Res<Windows>,
+    cameras: Query<(&Camera, &GlobalTransform)>,
+    buildings: Query<(Entity, &Handle<Mesh>, &GlobalTransform), With<BuildingIdx>>,
+    bounds: Query<&Aabb>,
+    meshes: Res<Assets<Mesh>>,
+    mut look: Query<&mut LookTransform>,
+) {
+    if *mode != CameraMode::Orbit
+        || !mouse.just_pressed(MouseButton::Left)
+        || ctx.ctx_mut().wants_pointer_input()
+    {
+        return;
+    }
+    let now = time.elapsed_seconds_f64();
+    let double = last_click.is_some_and(|last| now - last < DOUBLE_CLICK_SECONDS);
+    *last_click = Some(now);
+    if !double {
+        return;
+    }
+
+    let ray = if let Some(ray) = picking::cursor_ray(&windows, &cameras) {
+        ray
+    } else {
+        return;
+    };
+    let (entity, _) = if let Some(hit) = picking::pick_building(ray, &buildings, &meshes) {
+        hit
+    } else {
+        return;
+    };
+    let (transform, aabb) = match (buildings.get(entity), bounds.get(entity)) {
+        (Ok((_, _, transform)), Ok(aabb)) => (transform, aabb),
+        _ => return,
+    };
+    let target = transform.transform_point(aabb.center.into());
+    let size = transform
+        .affine()
+        .transform_vector3(aabb.half_extents.into())
+        .length();
+
+    for mut look in &mut look {
+        let direction = look.look_direction().unwrap_or(Vec3::NEG_Z);
+        // Close enough to see the whole building, but not so close it's awkward to orbit
+        let radius = (4.0 * size).max(30.0);
+        look.target = target;
+        look.eye = target - radius * direction;
+    }
+}
+
+/// Looks at everything from the current direction
+pub fn frame_all(city: &City, look: &mut LookTransform) {
+    let direction = look
+        .look_direction()
+        .filter(|dir| dir.y < -0.1)
+        .unwrap_or_else(|| Vec3::new(0.4, -0.6, -0.1).normalize());
+    // The default vertical field of view is 45 degrees
+    let radius = city.extent / 2.0 / (std::f32::consts::FRAC_PI_8).tan();
+    look.target = Vec3::ZERO;
+    look.eye = look.target - radius * direction;
+}
+
+pub fn frame_all_key(
+    keyboard: Res<Input<KeyCode>>,
+    city: Res<City>,
+    mut look: Query<&mut LookTransform>,
+) {
+    if keyboard.just_pressed(KeyCode::Home) {
+        for mut look in &mut look {
+            frame_all(&city, &mut look);
+        }
+    }
+}
+
+pub fn controls(
+    mut ctx: ResMut<EguiContext>,
+    mut mode: ResMut<CameraMode>,
+    city: Res<City>,
+    mut look: Query<&mut LookTransform>,
+) {
+    let mut new_mode = *mode;
+    egui::Window::new("Camera").show(ctx.ctx_mut(), |ui| {
+        ui.horizontal(|ui| {
+            ui.radio_value(&mut new_mode, CameraMode::Fps, "fly");
+            ui.radio_value(&mut new_mode, CameraMode::Orbit, "orbit");
+        });
+        match new_mode {
+            CameraMode::Fps => ui.label("Arrow keys to move and turn, Q/A to look up and down"),
+            CameraMode::Orbit => ui.label(
+                "Drag to orbit, right drag to pan, scroll to zoom, double click a building to focus",
+            ),
+        };
+        if ui.button("Frame all (Home)").clicked() {
+            for mut look in &mut look {
+                frame_all(&city, &mut look);
+            }
+        }
+    });
+    if new_mode != *mode {
+        *mode = new_mode;
+    }
+}
@@ -0,0 +1,37 @@
+use bevy::prelude::{
+    default, BuildChildren, Camera, Camera3dBundle, Commands, Entity, Projection, Query, Res, With,
+    Without,
+};
+use common::capture::{Capture, CaptureCamera};
+use smooth_bevy_cameras::LookTransform;
+
+/// Once recording starts, renders the main camera's view into images too
+#[allow(clippy::type_complexity)]
+pub fn spawn_camera(
+    mut commands: Commands,
+    capture: Res<Capture>,
+    main_camera: Query<(Entity, &Projection), (With<LookTransform>, Without<CaptureCamera>)>,
+    capture_camera: Query<(), With<CaptureCamera>>,
+) {
+    let export = match capture.export() {
+        Some(export) if capture_camera.is_empty() => export,
+        _ => return,
+    };
+    for (entity, projection) in &main_camera {
+        commands.entity(entity).with_children(|parent| {
+            parent.spawn((
+                Camera3dBundle {
+                    camera: Camera {
+                        // Draw before the main camera, which clears the window anyway
+                        priority: -1,
+                        ..default()
+                    },
+                    projection: projection.clone(),
+                    ..default()
+                },
+                export.clone(),
+                CaptureCamera,
+            ));
+        });
+    }
+}
@@ -0,0 +1,343 @@
+use std::collections::HashMap;
+
+use bevy::prelude::{Color, Component, Query, Res, ResMut, Resource};
+use bevy_egui::egui;
+use geo::Area;
+use random_color::RandomColor;
+
+use crate::animation::BuildingAnimation;
+use crate::buildings::{Building, City};
+use crate::Seed;
+
+// Categories beyond this many share one colour
+const MAX_CATEGORIES: usize = 10;
+
+// Tableau 10
+const CATEGORY_PALETTE: [[u8; 3]; MAX_CATEGORIES] = [
+    [78, 121, 167],
+    [242, 142, 43],
+    [225, 87, 89],
+    [118, 183, 178],
+    [89, 161, 79],
+    [237, 201, 72],
+    [176, 122, 161],
+    [255, 157, 167],
+    [156, 117, 95],
+    [186, 176, 172],
+];
+
+// Viridis, from low to high
+const RAMP: [[u8; 3]; 5] = [
+    [68, 1, 84],
+    [59, 82, 139],
+    [33, 145, 140],
+    [94, 201, 98],
+    [253, 231, 37],
+];
+
+const MISSING: [u8; 3] = [90, 90, 90];
+
+/// Which building in `City` an entity came from
+#[derive(Component)]
+pub struct BuildingIdx(pub usize);
+
+#[derive(Resource, Clone, PartialEq)]
+pub struct ColourScheme {
+    pub by: ColourBy,
+    /// The property to group by, for `ColourBy::Category`
+    pub category_key: String,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum ColourBy {
+    Random,
+    Category,
+    Height,
+    Year,
+    Area,
+}
+
+impl ColourBy {
+    const ALL: [ColourBy; 5] = [
+        ColourBy::Random,
+        ColourBy::Category,
+        ColourBy::Height,
+        ColourBy::Year,
+        ColourBy::Area,
+    ];
+
+    fn name(self) -> &'static str {
+        match self {
+            ColourBy::Random => "random",
+            ColourBy::Category => "category",
+            ColourBy::Height => "height",
+            ColourBy::Year => "construction year",
+            ColourBy::Area => "footprint area",
+        }
+    }
+
+    fn value(self, building: &Building) -> Option<f64> {
+        match self {
+            ColourBy::Random | ColourBy::Category => None,
+            ColourBy::Height => Some(building.height as f64),
+            ColourBy::Year => construction_year(building),
+            ColourBy::Area => Some(building.polygon.unsigned_area()),
+        }
+    }
+
+    fn unit(self) -> &'static str {
+        match self {
+            ColourBy::Height => "m",
+            ColourBy::Area => "m²",
+            _ => "",
+        }
+    }
+}
+
+impl Default for ColourScheme {
+    fn default() -> Self {
+        Self {
+            by: ColourBy::Random,
+            category_key: "building".to_string(),
+        }
+    }
+}
+
+/// What each colour means, for the current scheme
+#[derive(Resource, Default)]
+pub struct Legend(Vec<(String, Color)>);
+
+/// A colour in the blue family, the same for a building every time with the same seed
+pub fn random_colour(seed: u64, building: &Building) -> Color {
+    let [r, g, b] = RandomColor::new()
+        .hue(random_color::Color::Blue)
+        .seed(stable_hash(seed, &building.name))
+        .to_rgb_array();
+    Color::rgb_u8(r, g, b)
+}
+
+// FNV-1a. std's hasher isn't guaranteed to stay the same between Rust versions.
+fn stable_hash(seed: u64, name: &str) -> i64 {
+    let mut hash = 0xcbf29ce484222325_u64 ^ seed;
+    for byte in name.bytes() {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash as i64
+}
+
+// Handles "1923", "1923-05-01", "~1900", and "before 1850"
+fn construction_year(building: &Building) -> Option<f64> {
+    for key in ["start_date", "building:start_date", "construction_year"] {
+        let value = match building.properties.get(key) {
+            Some(value) => value,
+            None => continue,
+        };
+        if let Some(x) = value.as_f64() {
+            return Some(x);
+        }
+        // A key that isn't a year might still be followed by one that is
+        let text = match value.as_str() {
+            Some(text) => text,
+            None => continue,
+        };
+        let digits: String = text
+            .chars()
+            .skip_while(|c| !c.is_ascii_digit())
+            .take_while(|c| c.is_ascii_digit())
+            .collect();
+        if digits.len() == 4 {
+            return digits.parse().ok();
+        }
+    }
+    None
+}
+
+fn category(building: &Building, key: &str) -> Option<String> {
+    let value = building.properties.get(key)?;
+    Some(match value.as_str() {
+        Some(x) => x.to_string(),
+        None => value.to_string(),
+    })
+}
+
+fn rgb([r, g, b]: [u8; 3]) -> Color {
+    Color::rgb_u8(r, g, b)
+}
+
+// x is from 0 to 1
+fn sample_ramp(x: f64) -> Color {
+    let x = x.clamp(0.0, 1.0) * (RAMP.len() - 1) as f64;
+    let idx = (x.floor() as usize).min(RAMP.len() - 2);
+    let t = (x - idx as f64) as f32;
+    let [r1, g1, b1] = RAMP[idx].map(|c| c as f32);
+    let [r2, g2, b2] = RAMP[idx + 1].map(|c| c as f32);
+    let lerp = |a: f32, b: f32| (a + t * (b - a)) / 255.0;
+    Color::rgb(lerp(r1, r2), lerp(g1, g2), lerp(b1, b2))
+}
+
+/// Returns the colour of every building, and the legend explaining them
+fn assign(scheme: &ColourScheme, city: &City, seed: u64) -> (Vec<Color>, Vec<(String, Color)>) {
+    match scheme.by {
+        ColourBy::Random => (
+            city.buildings
+                .iter()
+                .map(|b| random_colour(seed, b))
+                .collect(),
+            Vec::new(),
+        ),
+        ColourBy::Category => {
+            let values: Vec<Option<String>> = city
+                .buildings
+                .iter()
+                .map(|b| category(b, &scheme.category_key))
+                .collect();
+            let mut counts: HashMap<&str, usize> = HashMap::new();
+            for value in values.iter().flatten() {
+                *counts.entry(value).or_insert(0) += 1;
+            }
+            // The most common values get their own colour. Break ties by name, so the legend
+            // doesn't jump around.
+            let mut ranked: Vec<(&str, usize)> = counts.into_iter().collect();
+            ranked.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+
+            let mut palette: HashMap<&str, Color> = HashMap::new();
+            let mut legend = Vec::new();
+            for (idx, (value, count)) in ranked.iter().enumerate() {
+                if idx < MAX_CATEGORIES {
+                    let colour = rgb(CATEGORY_PALETTE[idx]);
+                    palette.insert(value, colour);
+                    legend.push((format!("{value} ({count})"), colour));
+                }
+            }
+            let other: usize = ranked.iter().skip(MAX_CATEGORIES).map(|(_, n)| n).sum();
+            if other > 0 {
+                legend.push((format!("other ({other})"), rgb(MISSING)));
+            }
+            let missing = values.iter().filter(|v| v.is_none()).count();
+            if missing > 0 {
+                legend.push((
+                    format!("no {} ({missing})", scheme.category_key),
+                    rgb(MISSING),
+                ));
+            }
+
+            let colours = values
+                .iter()
+                .map(|v| {
+                    v.as_ref()
+                        .and_then(|v| palette.get(v.as_str()).copied())
+                        .unwrap_or_else(|| rgb(MISSING))
+                })
+                .collect();
+            (colours, legend)
+        }
+        by => {
+            let values: Vec<Option<f64>> = city.buildings.iter().map(|b| by.value(b)).collect();
+            let (min, max) = values
+                .iter()
+                .flatten()
+                .fold((f64::MAX, f64::MIN), |(lo, hi), x| (lo.min(*x), hi.max(*x)));
+            if min > max {
+                return (
+                    vec![rgb(MISSING); values.len()],
+                    vec![(format!("no {}", by.name()), rgb(MISSING))],
+                );
+            }
+            let range = (max - min).max(f64::EPSILON);
+            let colours = values
+                .iter()
+                .map(|v| match v {
+                    Some(x) => sample_ramp((x - min) / range),
+                    None => rgb(MISSING),
+                })
+                .collect();
+
+            let mut legend = Vec::new();
+            for step in 0..RAMP.len() {
+                let x = step as f64 / (RAMP.len() - 1) as f64;
+                legend.push((
+                    format!("{:.0}{}", min + x * range, by.unit()),
+                    sample_ramp(x),
+                ));
+            }
+            let missing = values.iter().filter(|v| v.is_none()).count();
+            if missing > 0 {
+                legend.push((format!("unknown ({missing})"), rgb(MISSING)));
+            }
+            (colours, legend)
+        }
+    }
+}
+
+/// When the scheme changes, give every building its new base colour
+pub fn recolour(
+    scheme: Res<ColourScheme>,
+    city: Res<City>,
+    seed: Res<Seed>,
+    mut legend: ResMut<Legend>,
+    mut query: Query<(&BuildingIdx, &mut BuildingAnimation)>,
+) {
+    if !scheme.is_changed() {
+        return;
+    }
+    let (colours, entries) = assign(&scheme, &city, seed.seed);
+    legend.0 = entries;
+    // apply_animations notices the change and updates the material
+    for (idx, mut building) in &mut query {
+        building.base_colour = colours[idx.0];
+    }
+}
+
+pub fn controls(ui: &mut egui::Ui, scheme: &mut ColourScheme, legend: &Legend) {
+    ui.separator();
+    ui.heading("Colour by");
+    egui::ComboBox::from_id_source("colour by")
+        .selected_text(scheme.by.name())
+        .show_ui(ui, |ui| {
+            for by in ColourBy::ALL {
+                ui.selectable_value(&mut scheme.by, by, by.name());
+            }
+        });
+    if scheme.by == ColourBy::Category {
+        ui.horizontal(|ui| {
+            ui.label("Property");
+            ui.text_edit_singleline(&mut scheme.category_key);
+        });
+    }
+
+    for (label, colour) in &legend.0 {
+        ui.horizontal(|ui| {
+            let [r, g, b, _] = colour.as_rgba_f32().map(|x| (x * 255.0).round() as u8);
+            egui::color_picker::show_color(
+                ui,
+                egui::Color32::from_rgb(r, g, b),
+                egui::vec2(16.0, 16.0),
+            );
+            ui.label(label);
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use geo::Rect;
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn construction_year_falls_back_to_later_keys() {
+        let building = Building {
+            polygon: Rect::new((0.0, 0.0), (1.0, 1.0)).to_polygon(),
+            name: String::new(),
+            properties: json!({"start_date": null, "construction_year": "c. 1910"})
+                .as_object()
+                .unwrap()
+                .clone(),
+            height: 0.0,
+            base: 0.0,
+        };
+        assert_eq!(construction_year(&building), Some(1910.0));
+    }
+}
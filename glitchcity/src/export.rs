@@ -0,0 +1,182 @@
+use std::io::Write;
+
+use anyhow::{bail, Result};
+use bevy::prelude::Vec3;
+use serde_json::{json, Value};
+
+use crate::buildings::{self, Building};
+use crate::mesh::MeshBuilder;
+
+// From the glTF spec
+const ARRAY_BUFFER: u32 = 34962;
+const ELEMENT_ARRAY_BUFFER: u32 = 34963;
+const FLOAT: u32 = 5126;
+const UNSIGNED_INT: u32 = 5125;
+const TRIANGLES: u32 = 4;
+
+/// Write every building as a separate named node to a binary glTF file. The GeoJSON properties
+/// become the node's extras. Coordinates are in meters, Y-up like Bevy.
+pub fn write_glb(path: &str, buildings: &[Building]) -> Result<()> {
+    let mut bin: Vec<u8> = Vec::new();
+    let mut nodes = Vec::new();
+    let mut meshes = Vec::new();
+    let mut accessors = Vec::new();
+    let mut buffer_views = Vec::new();
+
+    for building in buildings {
+        let mut builder = MeshBuilder::new();
+        let centroid =
+            buildings::extrude_around_centroid(&building.polygon, building.height, &mut builder);
+
+        let mut min = [f32::MAX; 3];
+        let mut max = [f32::MIN; 3];
+        let mut positions = Vec::new();
+        let mut normals = Vec::new();
+        for vert in builder.vertices() {
+            for (i, value) in vert.pos.to_array().into_iter().enumerate() {
+                min[i] = min[i].min(value);
+                max[i] = max[i].max(value);
+            }
+            positions.extend(vert.pos.to_array());
+            normals.extend(vert.normal.to_array());
+        }
+        let num_vertices = builder.vertices().len();
+        if num_vertices == 0 {
+            continue;
+        }
+
+        let position = accessors.len();
+        accessors.push(json!({
+            "bufferView": add_view(&mut bin, &mut buffer_views, &positions, ARRAY_BUFFER),
+            "componentType": FLOAT,
+            "count": num_vertices,
+            "type": "VEC3",
+            "min": min,
+            "max": max,
+        }));
+        let normal = accessors.len();
+        accessors.push(json!({
+            "bufferView": add_view(&mut bin, &mut buffer_views, &normals, ARRAY_BUFFER),
+            "componentType": FLOAT,
+            "count": num_vertices,
+            "type": "VEC3",
+        }));
+        let indices = accessors.len();
+        let view = add_view(
+            &mut bin,
+            &mut buffer_views,
+            builder.indices(),
+            ELEMENT_ARRAY_BUFFER,
+        );
+        accessors.push(json!({
+            "bufferView": view,
+            "componentType": UNSIGNED_INT,
+            "count": builder.indices().len(),
+            "type": "SCALAR",
+        }));
+
+        nodes.push(json!({
+            "name": building.name,
+            "mesh": meshes.len(),
+            "translation": (centroid + building.base * Vec3::Y).to_array(),
+            "extras": building.properties,
+        }));
+        meshes.push(json!({
+            "name": building.name,
+            "primitives": [{
+                "attributes": {
+                    "POSITION": position,
+                    "NORMAL": normal,
+                },
+                "indices": indices,
+                "mode": TRIANGLES,
+            }],
+        }));
+    }
+
+    // glTF doesn't allow empty buffers or accessors
+    if nodes.is_empty() {
+        bail!("No buildings to export");
+    }
+
+    let gltf = json!({
+        "asset": {
+            "version": "2.0",
+            "generator": "glitchcity",
+        },
+        "scene": 0,
+        "scenes": [{
+            "nodes": (0..nodes.len()).collect::<Vec<_>>(),
+        }],
+        "nodes": nodes,
+        "meshes": meshes,
+        "accessors": accessors,
+        "bufferViews": buffer_views,
+        "buffers": [{
+            "byteLength": bin.len(),
+        }],
+    });
+
+    let mut json_chunk = serde_json::to_vec(&gltf)?;
+    // Chunks must be 4-byte aligned. JSON is padded with spaces, binary with zeroes.
+    while !json_chunk.len().is_multiple_of(4) {
+        json_chunk.push(b' ');
+    }
+    while !bin.len().is_multiple_of(4) {
+        bin.push(0);
+    }
+
+    let total_length = 12 + 8 + json_chunk.len() + 8 + bin.len();
+    let mut file = std::io::BufWriter::new(std::fs::File::create(path)?);
+    file.write_all(b"glTF")?;
+    file.write_all(&2_u32.to_le_bytes())?;
+    file.write_all(&(total_length as u32).to_le_bytes())?;
+
+    file.write_all(&(json_chunk.len() as u32).to_le_bytes())?;
+    file.write_all(b"JSON")?;
+    file.write_all(&json_chunk)?;
+
+    file.write_all(&(bin.len() as u32).to_le_bytes())?;
+    file.write_all(b"BIN\0")?;
+    file.write_all(&bin)?;
+    file.flush()?;
+
+    println!("Wrote {} buildings to {path}", buildings.len());
+    Ok(())
+}
+
+// Both f32 and u32 are 4 bytes, so every view stays aligned without padding
+fn add_view<T: ToLeBytes>(
+    bin: &mut Vec<u8>,
+    buffer_views: &mut Vec<Value>,
+    values: &[T],
+    target: u32,
+) -> usize {
+    let offset = bin.len();
+    for x in values {
+        bin.extend(x.to_le_bytes());
+    }
+    buffer_views.push(json!({
+        "buffer": 0,
+        "byteOffset": offset,
+        "byteLength": bin.len() - offset,
+        "target": target,
+    }));
+    buffer_views.len() - 1
+}
+
+trait ToLeBytes {
+    fn to_le_bytes(&self) -> [u8; 4];
+}
+
+impl ToLeBytes for f32 {
+    fn to_le_bytes(&self) -> [u8; 4] {
+        f32::to_le_bytes(*self)
+    }
+}
+
+impl ToLeBytes for u32 {
+    fn to_le_bytes(&self) -> [u8; 4] {
+        u32::to_le_bytes(*self)
+    }
+}
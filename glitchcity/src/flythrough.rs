@@ -0,0 +1,284 @@
+use std::time::Duration;
+
+use anyhow::{bail, Result};
+use bevy::prelude::{Commands, Entity, Query, ResMut, Resource, Transform, Vec3};
+use bevy_egui::{egui, EguiContext};
+use bevy_tweening::{Animator, EaseMethod, Lens, Tween};
+use common::capture::{self, Capture};
+use serde::{Deserialize, Serialize};
+use smooth_bevy_cameras::controllers::fps::FpsCameraController;
+use smooth_bevy_cameras::controllers::orbit::OrbitCameraController;
+use smooth_bevy_cameras::LookTransform;
+
+// A new keyframe comes this long after the previous one, by default
+const SECONDS_BETWEEN_KEYFRAMES: f32 = 3.0;
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Keyframe {
+    /// Where the camera is, in world space
+    pub eye: [f32; 3],
+    /// What the camera looks at, in world space
+    pub target: [f32; 3],
+    /// Seconds from the start of the fly-through
+    pub time: f32,
+}
+
+#[derive(Resource)]
+pub struct FlyThrough {
+    pub keyframes: Vec<Keyframe>,
+    /// Where to save and load
+    pub path: String,
+    /// The result of the last save or load
+    status: String,
+    /// Stop capturing frames when playback ends
+    recording: bool,
+}
+
+impl FlyThrough {
+    pub fn new(path: String) -> Self {
+        Self {
+            keyframes: Vec::new(),
+            path,
+            status: String::new(),
+            recording: false,
+        }
+    }
+
+    pub fn load(&mut self) -> Result<()> {
+        let mut keyframes: Vec<Keyframe> =
+            serde_json::from_str(&std::fs::read_to_string(&self.path)?)?;
+        keyframes.sort_by(|a, b| a.time.total_cmp(&b.time));
+        self.keyframes = keyframes;
+        Ok(())
+    }
+
+    fn save(&self) -> Result<()> {
+        std::fs::write(&self.path, serde_json::to_string_pretty(&self.keyframes)?)?;
+        Ok(())
+    }
+
+    /// A tween passing smoothly through every keyframe
+    pub fn tween(&self) -> Result<Tween<LookTransform>> {
+        let mut keyframes = self.keyframes.clone();
+        keyframes.sort_by(|a, b| a.time.total_cmp(&b.time));
+        if keyframes.len() < 2 {
+            bail!("Add at least two keyframes");
+        }
+        let start = keyframes[0].time;
+        let duration = keyframes.last().unwrap().time - start;
+        if duration <= 0.0 {
+            bail!("The keyframes all happen at the same time");
+        }
+        for keyframe in &mut keyframes {
+            keyframe.time -= start;
+        }
+        Ok(Tween::new(
+            EaseMethod::Linear,
+            Duration::from_secs_f32(duration),
+            PathLens {
+                keyframes,
+                duration,
+            },
+        ))
+    }
+}
+
+/// Moves the camera along a Catmull-Rom spline through the keyframes
+struct PathLens {
+    /// Sorted by time, starting at 0
+    keyframes: Vec<Keyframe>,
+    duration: f32,
+}
+
+impl Lens<LookTransform> for PathLens {
+    fn lerp(&mut self, target: &mut LookTransform, ratio: f32) {
+        let time = ratio * self.duration;
+        let last = self.keyframes.len() - 1;
+        // The segment from keyframe i to i + 1
+        let i = self
+            .keyframes
+            .partition_point(|k| k.time <= time)
+            .saturating_sub(1)
+            .min(last - 1);
+        let (k1, k2) = (&self.keyframes[i], &self.keyframes[i + 1]);
+        let t = if k2.time > k1.time {
+            ((time - k1.time) / (k2.time - k1.time)).clamp(0.0, 1.0)
+        } else {
+            1.0
+        };
+        // Repeat the endpoints
+        let k0 = &self.keyframes[i.saturating_sub(1)];
+        let k3 = &self.keyframes[(i + 2).min(last)];
+
+        target.eye = catmull_rom([k0.eye, k1.eye, k2.eye, k3.eye].map(Vec3::from), t);
+        target.target = catmull_rom(
+            [k0.target, k1.target, k2.target, k3.target].map(Vec3::from),
+            t,
+        );
+    }
+}
+
+fn catmull_rom([p0, p1, p2, p3]: [Vec3; 4], t: f32) -> Vec3 {
+    let t2 = t * t;
+    let t3 = t2 * t;
+    0.5 * ((2.0 * p1)
+        + (p2 - p0) * t
+        + (2.0 * p0 - 5.0 * p1 + 4.0 * p2 - p3) * t2
+        + (3.0 * p1 - p0 - 3.0 * p2 + p3) * t3)
+}
+
+/// While a fly-through plays, only the tween moves the camera. The FPS controller would keep
+/// adjusting the target, and smoothing lags by an amount that depends on the frame rate, so a
+/// recording wouldn't be repeatable. Disabling a controller also disables its smoother, which
+/// normally updates the transform, so do that here instead.
+#[allow(clippy::type_complexity)]
+pub fn drive_camera(
+    mut query: Query<(
+        &LookTransform,
+        &mut Transform,
+        Option<&mut FpsCameraController>,
+        Option<&mut OrbitCameraController>,
+        Option<&Animator<LookTransform>>,
+    )>,
+) {
+    for (look, mut transform, fps, orbit, animator) in &mut query {
+        let playing = animator.is_some();
+        // Only write on a change, since the controllers reset their smoothers then
+        if let Some(mut fps) = fps {
+            if fps.enabled == playing {
+                fps.enabled = !playing;
+            }
+        }
+        if let Some(mut orbit) = orbit {
+            if orbit.enabled == playing {
+                orbit.enabled = !playing;
+            }
+        }
+        if playing {
+            *transform = (*look).into();
+        }
+    }
+}
+
+/// Stop driving the camera once the fly-through is over, so the controllers take back over
+pub fn finish_playback(
+    mut commands: Commands,
+    mut flythrough: ResMut<FlyThrough>,
+    mut capture: ResMut<Capture>,
+    query: Query<(Entity, &Animator<LookTransform>)>,
+) {
+    for (entity, animator) in &query {
+        if animator.tweenable().progress() >= 1.0 {
+            commands.entity(entity).remove::<Animator<LookTransform>>();
+            if flythrough.recording {
+                flythrough.recording = false;
+                capture.stop();
+            }
+        }
+    }
+}
+
+pub fn controls(
+    mut ctx: ResMut<EguiContext>,
+    mut flythrough: ResMut<FlyThrough>,
+    mut capture: ResMut<Capture>,
+    mut commands: Commands,
+    camera: Query<(Entity, &LookTransform, Option<&Animator<LookTransform>>)>,
+) {
+    let (entity, look, animator) = if let Ok(camera) = camera.get_single() {
+        camera
+    } else {
+        return;
+    };
+    let flythrough = &mut *flythrough;
+
+    egui::Window::new("Fly-through").show(ctx.ctx_mut(), |ui| {
+        ui.horizontal(|ui| {
+            if ui.button("Add keyframe").clicked() {
+                let time = flythrough
+                    .keyframes
+                    .iter()
+                    .map(|k| k.time + SECONDS_BETWEEN_KEYFRAMES)
+                    .reduce(f32::max)
+                    .unwrap_or(0.0);
+                flythrough.keyframes.push(Keyframe {
+                    eye: look.eye.to_array(),
+                    target: look.target.to_array(),
+                    time,
+                });
+            }
+            if animator.is_some() {
+                if ui.button("Stop").clicked() {
+                    commands.entity(entity).remove::<Animator<LookTransform>>();
+                    if flythrough.recording {
+                        flythrough.recording = false;
+                        capture.stop();
+                    }
+                }
+            } else {
+                let play = ui.button("Play").clicked();
+                let record = ui.button("Play and record").clicked();
+                if play || record {
+                    match flythrough.tween() {
+                        Ok(tween) => {
+                            commands.entity(entity).insert(Animator::new(tween));
+                            flythrough.status.clear();
+                            if record {
+                                flythrough.recording = true;
+                                capture.start();
+                            }
+                        }
+                        Err(err) => flythrough.status = err.to_string(),
+                    }
+                }
+            }
+        });
+
+        let mut remove = None;
+        for (idx, keyframe) in flythrough.keyframes.iter_mut().enumerate() {
+            ui.horizontal(|ui| {
+                ui.label(format!("#{}", idx + 1));
+                ui.add(
+                    egui::DragValue::new(&mut keyframe.time)
+                        .speed(0.1)
+                        .clamp_range(0.0..=f32::MAX)
+                        .suffix(" s"),
+                );
+                if ui.button("Go").clicked() {
+                    commands.entity(entity).insert(LookTransform::new(
+                        Vec3::from(keyframe.eye),
+                        Vec3::from(keyframe.target),
+                    ));
+                }
+                if ui.button("Delete").clicked() {
+                    remove = Some(idx);
+                }
+            });
+        }
+        if let Some(idx) = remove {
+            flythrough.keyframes.remove(idx);
+        }
+
+        ui.separator();
+        ui.horizontal(|ui| {
+            ui.text_edit_singleline(&mut flythrough.path);
+            if ui.button("Save").clicked() {
+                flythrough.status = match flythrough.save() {
+                    Ok(()) => format!("Saved {}", flythrough.path),
+                    Err(err) => err.to_string(),
+                };
+            }
+            if ui.button("Load").clicked() {
+                flythrough.status = match flythrough.load() {
+                    Ok(()) => format!("Loaded {}", flythrough.path),
+                    Err(err) => err.to_string(),
+                };
+            }
+        });
+        if !flythrough.status.is_empty() {
+            ui.label(&flythrough.status);
+        }
+
+        capture::controls(ui, &mut capture);
+    });
+}
@@ -1,172 +1,423 @@
-use anyhow::Result;
+use anyhow::{bail, Result};
 use bevy::prelude::{
-    default, App, Assets, Camera3dBundle, Color, Commands, DefaultPlugins, EventWriter, Input,
-    KeyCode, Mesh, PbrBundle, PointLight, PointLightBundle, Quat, Query, Res, ResMut,
-    StandardMaterial, Transform, Vec2, Vec3,
+    default, shape, AlphaMode, App, Assets, Camera3dBundle, Color, Commands, CoreStage,
+    DefaultPlugins, DirectionalLight, DirectionalLightBundle, Input, IntoSystemDescriptor, KeyCode,
+    Mesh, OrthographicProjection, PbrBundle, PerspectiveProjection, Res, ResMut, Resource,
+    StandardMaterial, Transform, Vec3,
 };
+use bevy::winit::WinitSettings;
 use bevy_egui::{egui, EguiContext};
+use bevy_image_export::ImageExportPlugin;
 use bevy_inspector_egui::WorldInspectorPlugin;
-use bevy_tweening::lens::{TransformRotationLens, TransformScaleLens};
-use bevy_tweening::{
-    Animator, AnimatorState, EaseFunction, RepeatCount, RepeatStrategy, Tracks, Tween,
-    TweeningPlugin,
-};
-use rand::Rng;
-use random_color::RandomColor;
+use bevy_tweening::{component_animator_system, Animator, TweeningPlugin};
+use common::args::CmdArgs;
+use common::capture::Capture;
+use common::projection::Projection;
+use geo::{BoundingRect, Coord, MultiPolygon, Rect};
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
 use smooth_bevy_cameras::{
-    controllers::fps::{ControlEvent, FpsCameraBundle, FpsCameraController, FpsCameraPlugin},
-    LookTransformPlugin,
+    controllers::fps::{FpsCameraBundle, FpsCameraController, FpsCameraPlugin},
+    controllers::orbit::OrbitCameraPlugin,
+    LookTransform, LookTransformPlugin,
 };
-use std::time::Duration;
 
+use self::animation::{AnimationSettings, BuildingAnimation, ColourShift, HeightPulse, Spin};
+use self::buildings::{City, VerticalExaggeration};
+use self::camera::CameraMode;
+use self::colours::{BuildingIdx, ColourScheme, Legend};
+use self::flythrough::FlyThrough;
+use self::print::PrintSettings;
+use self::selection::Selection;
+use self::sun::{Sun, SunSettings};
+use self::terrain::{Dem, Terrain};
+use self::water::{FloodStats, Water, WaterSettings};
+
+mod animation;
 mod buildings;
+mod camera;
+mod capture;
+mod colours;
+mod export;
+mod flythrough;
 mod mesh;
+mod picking;
+mod print;
+mod selection;
+mod sun;
+mod terrain;
+mod water;
 
 fn main() -> Result<()> {
-    App::new()
+    let mut args = CmdArgs::new();
+    let path = args.required_free();
+    let export_glb = args.optional("--export-glb");
+    let export_stl = args.optional("--export-stl");
+    let export_obj = args.optional("--export-obj");
+    let area = args.optional("--area");
+    let dem = args.optional("--dem");
+    let capture = Capture::from_args(&mut args)?;
+    let flythrough_path = args
+        .optional("--flythrough")
+        .unwrap_or_else(|| "flythrough.json".to_string());
+    let vertical_exaggeration = args.optional_parse("--exaggeration").unwrap_or(1.0);
+    let seed = args
+        .optional_parse("--seed")
+        .unwrap_or_else(|| rand::thread_rng().gen());
+    let print_settings = PrintSettings {
+        area: None,
+        scale: args.optional_parse("--scale").unwrap_or(5000.0),
+        vertical_exaggeration,
+        base_mm: args.optional_parse("--base-mm").unwrap_or(2.0),
+    };
+    args.done();
+
+    let (mut buildings, projection) = mesh::load_polygons(&path)?;
+    // Print the seed, so a run can be reproduced later
+    println!("Using --seed={seed}");
+    let mut rng = ChaCha8Rng::seed_from_u64(seed);
+    for building in &mut buildings {
+        // Make something up when OSM doesn't say
+        building.height = building
+            .height_from_tags()
+            .unwrap_or_else(|| rng.gen_range(6.0..30.0));
+    }
+    let bbox = MultiPolygon::new(buildings.iter().map(|b| b.polygon.clone()).collect())
+        .bounding_rect()
+        .unwrap();
+    let extent = bbox.width().hypot(bbox.height()) as f32;
+
+    let terrain = if let Some(path) = dem {
+        let terrain = Terrain::new(&Dem::load(&path)?, projection, bbox, extent as f64 / 10.0)?;
+        for building in &mut buildings {
+            building.base = terrain.base_elevation(&building.polygon);
+        }
+        Some(terrain)
+    } else {
+        None
+    };
+
+    // Export without ever opening a window
+    let print = export_stl.is_some() || export_obj.is_some();
+    if area.is_some() && (!print || export_glb.is_some()) {
+        bail!("--area only crops --export-stl and --export-obj, not --export-glb or the window");
+    }
+    if let Some(ref path) = export_glb {
+        export::write_glb(path, &buildings)?;
+    }
+    if print {
+        let print_settings = PrintSettings {
+            area: area.map(|area| parse_area(&area, projection)).transpose()?,
+            ..print_settings
+        };
+        let mesh = print::build(&buildings, &print_settings)?;
+        let bad_edges = mesh.count_bad_edges();
+        if bad_edges > 0 {
+            println!("Warning: {bad_edges} edges aren't manifold");
+        }
+        if let Some(path) = export_stl {
+            mesh.write_stl(&path)?;
+        }
+        if let Some(path) = export_obj {
+            mesh.write_obj(&path)?;
+        }
+    }
+    if export_glb.is_some() || print {
+        return Ok(());
+    }
+
+    let mut flythrough = FlyThrough::new(flythrough_path);
+    if std::path::Path::new(&flythrough.path).exists() {
+        // Don't let a broken file stop the city from opening
+        if let Err(err) = flythrough.load() {
+            println!("Warning: couldn't load {}: {err}", flythrough.path);
+        }
+    }
+
+    let center = projection.to_lonlat(Coord::zero());
+    let export_plugin = ImageExportPlugin::default();
+    let export_threads = export_plugin.threads.clone();
+
+    let mut app = App::new();
+    if let Some(terrain) = terrain {
+        app.insert_resource(terrain);
+    }
+    app.insert_resource(City { buildings, extent })
+        .insert_resource(VerticalExaggeration(vertical_exaggeration))
+        .insert_resource(SunSettings::new(center.x, center.y))
+        .insert_resource(Seed { seed, rng })
+        // Return from run(), so frames still being saved can finish
+        .insert_resource(WinitSettings {
+            return_from_run: true,
+            ..default()
+        })
         .add_plugins(DefaultPlugins)
+        .add_plugin(export_plugin)
         .add_plugin(LookTransformPlugin)
         .add_plugin(FpsCameraPlugin::new(true))
+        .add_plugin(OrbitCameraPlugin::new(true))
         .add_plugin(WorldInspectorPlugin::new())
         .add_plugin(TweeningPlugin)
         .add_startup_system(setup)
+        .init_resource::<AnimationSettings>()
+        .add_system(component_animator_system::<HeightPulse>)
+        .add_system(component_animator_system::<Spin>)
+        .add_system(component_animator_system::<ColourShift>)
+        .add_system(animation::reset_phases.before(animation::sync_tracks))
+        .add_system(animation::sync_tracks)
+        .add_system(
+            animation::apply_animations
+                .after(component_animator_system::<HeightPulse>)
+                .after(component_animator_system::<Spin>)
+                .after(component_animator_system::<ColourShift>),
+        )
+        .init_resource::<ColourScheme>()
+        .init_resource::<Legend>()
+        .add_system(colours::recolour.before(animation::apply_animations))
+        .init_resource::<WaterSettings>()
+        .init_resource::<FloodStats>()
+        .add_system(
+            water::rise
+                .before(water::update_water)
+                .before(water::flood_buildings),
+        )
+        .add_system(water::update_water)
+        .add_system(water::flood_buildings.before(animation::apply_animations))
+        .add_system(sun::animate_sun.before(sun::update_sun))
+        .add_system(sun::update_sun)
+        .add_system(animation::pick_wave_origin)
+        .init_resource::<Selection>()
+        .add_system(selection::select_on_click)
+        .add_system(
+            selection::highlight_selection
+                .after(selection::select_on_click)
+                .before(animation::apply_animations),
+        )
+        .add_system(selection::inspector)
         .add_system(gui_controls)
-        .add_system(camera_controls)
+        .insert_resource(CameraMode::Fps)
+        .insert_resource(flythrough)
+        .add_system(camera::switch_mode)
+        .add_system(camera::fps_controls)
+        .add_system(camera::orbit_controls)
+        .add_system(camera::focus_on_double_click)
+        .add_system(camera::frame_all_key)
+        .add_system(camera::controls)
+        .add_system(component_animator_system::<LookTransform>)
+        .add_system(flythrough::drive_camera.after(component_animator_system::<LookTransform>))
+        .add_system(flythrough::finish_playback)
+        .add_system(flythrough::controls)
+        .insert_resource(capture)
+        .add_system(common::capture::toggle_key)
+        .add_system_to_stage(
+            CoreStage::Last,
+            capture::spawn_camera.before(common::capture::capture_frames),
+        )
+        .add_system_to_stage(CoreStage::Last, common::capture::capture_frames)
+        .add_system_to_stage(
+            CoreStage::Last,
+            common::capture::follow_parent::<bevy::prelude::Projection>,
+        )
         .run();
 
+    export_threads.finish();
+
     Ok(())
 }
 
+/// Every random choice comes from here, so the same seed looks the same
+#[derive(Resource)]
+struct Seed {
+    seed: u64,
+    rng: ChaCha8Rng,
+}
+
+// Parses "lon1,lat1,lon2,lat2"
+fn parse_area(area: &str, projection: Projection) -> Result<Rect> {
+    let numbers = area
+        .split(',')
+        .map(|x| x.parse::<f64>())
+        .collect::<Result<Vec<_>, _>>()?;
+    if numbers.len() != 4 {
+        bail!("--area should be lon1,lat1,lon2,lat2, not {area}");
+    }
+    Ok(Rect::new(
+        projection.to_meters(Coord {
+            x: numbers[0],
+            y: numbers[1],
+        }),
+        projection.to_meters(Coord {
+            x: numbers[2],
+            y: numbers[3],
+        }),
+    ))
+}
+
 fn setup(
     mut commands: Commands,
     mut meshes: ResMut<Assets<Mesh>>,
     mut materials: ResMut<Assets<StandardMaterial>>,
+    city: Res<City>,
+    mut seed: ResMut<Seed>,
+    terrain: Option<Res<Terrain>>,
 ) {
-    let mut args: Vec<String> = std::env::args().collect();
-    if args.len() != 2 {
-        panic!("Pass a path to a .geojson containing some polygons");
-    }
-    let path = args.pop().unwrap();
-
-    let mut rng = rand::thread_rng();
-
-    for polygon in mesh::load_polygons(&path).unwrap() {
+    for (idx, building) in city.buildings.iter().enumerate() {
         let mut builder = mesh::MeshBuilder::new();
-        let height = rng.gen_range(200.0..500.0);
-        buildings::extrude(polygon, height, &mut builder);
-
-        let scale_height = Tween::new(
-            EaseFunction::QuadraticInOut,
-            Duration::from_secs(2),
-            TransformScaleLens {
-                start: Vec3::new(0.01, 0.01, 0.01),
-                end: Vec3::new(0.01, 0.04, 0.01),
-            },
-        )
-        .with_repeat_count(RepeatCount::Infinite)
-        .with_repeat_strategy(RepeatStrategy::MirroredRepeat);
-
-        // TODO Need to express the spin around the polygon's center, oops
-        let spin = Tween::new(
-            EaseFunction::QuadraticInOut,
-            Duration::from_secs(5),
-            TransformRotationLens {
-                start: Quat::IDENTITY,
-                end: Quat::from_rotation_y(180_f32.to_radians()),
-            },
-        )
-        .with_repeat_count(RepeatCount::Infinite)
-        .with_repeat_strategy(RepeatStrategy::MirroredRepeat);
+        let centroid =
+            buildings::extrude_around_centroid(&building.polygon, building.height, &mut builder);
+        let base_color = colours::random_colour(seed.seed, building);
 
         commands.spawn((
             PbrBundle {
-                mesh: meshes.add(builder.build()).into(),
+                mesh: meshes.add(builder.build()),
                 material: materials.add(StandardMaterial {
-                    base_color: bevy_color(RandomColor::new().hue(random_color::Color::Blue)),
+                    base_color,
                     cull_mode: None,
                     double_sided: true,
                     ..default()
                 }),
+                transform: Transform::from_translation(centroid + building.base * Vec3::Y),
                 ..default()
             },
-            Animator::new(Tracks::new([scale_height, spin])),
+            BuildingIdx(idx),
+            BuildingAnimation {
+                centroid,
+                base_colour: base_color,
+                random_phase: seed.rng.gen(),
+                submerged: 0.0,
+                selected: false,
+            },
+            HeightPulse::default(),
+            Spin::default(),
+            ColourShift::default(),
+            Animator::new(animation::progress_tween::<HeightPulse>(0.0)),
+            Animator::new(animation::progress_tween::<Spin>(0.0)),
+            Animator::new(animation::progress_tween::<ColourShift>(0.0)),
         ));
     }
 
-    commands.spawn(PointLightBundle {
-        point_light: PointLight {
-            intensity: 1500.0,
-            shadows_enabled: true,
+    if let Some(terrain) = terrain {
+        commands.spawn(PbrBundle {
+            mesh: meshes.add(terrain.to_mesh()),
+            material: materials.add(StandardMaterial {
+                base_color: Color::rgb(0.45, 0.5, 0.4),
+                perceptual_roughness: 1.0,
+                ..default()
+            }),
+            ..default()
+        });
+    }
+
+    commands.spawn((
+        PbrBundle {
+            mesh: meshes.add(
+                shape::Plane {
+                    size: 2.0 * city.extent,
+                }
+                .into(),
+            ),
+            material: materials.add(StandardMaterial {
+                base_color: *water::WATER_COLOUR.as_rgba().set_a(0.7),
+                alpha_mode: AlphaMode::Blend,
+                perceptual_roughness: 0.1,
+                ..default()
+            }),
             ..default()
         },
-        transform: Transform::from_xyz(4.0, 8.0, 4.0),
-        ..default()
-    });
+        Water,
+    ));
+
+    // A point light can't reach across a whole city, so light from far away. The shadows have to
+    // cover everything.
+    // sun::update_sun points it
+    let half = city.extent / 2.0;
+    commands.spawn((
+        DirectionalLightBundle {
+            directional_light: DirectionalLight {
+                shadows_enabled: true,
+                shadow_projection: OrthographicProjection {
+                    left: -half,
+                    right: half,
+                    bottom: -half,
+                    top: half,
+                    near: -city.extent,
+                    far: city.extent,
+                    ..default()
+                },
+                ..default()
+            },
+            ..default()
+        },
+        Sun,
+    ));
 
     commands
-        .spawn(Camera3dBundle::default())
+        .spawn(Camera3dBundle {
+            projection: PerspectiveProjection {
+                far: 10.0 * city.extent,
+                ..default()
+            }
+            .into(),
+            ..default()
+        })
         .insert(FpsCameraBundle::new(
             FpsCameraController {
                 smoothing_weight: 0.0,
                 ..default()
             },
             // eye
-            Vec3::new(-20.0, 35.0, 5.0),
+            city.extent * Vec3::new(-0.4, 0.6, 0.1),
             // target
             Vec3::splat(0.0),
         ));
 }
 
-fn bevy_color(c: &mut RandomColor) -> Color {
-    let [r, g, b] = c.to_rgb_array();
-    Color::rgb_u8(r, g, b)
-}
-
+#[allow(clippy::too_many_arguments)]
 fn gui_controls(
     mut ctx: ResMut<EguiContext>,
-    mut query: Query<&mut Animator<Transform>>,
+    mut settings: ResMut<AnimationSettings>,
+    mut exaggeration: ResMut<VerticalExaggeration>,
+    mut scheme: ResMut<ColourScheme>,
+    legend: Res<Legend>,
+    mut sun: ResMut<SunSettings>,
+    mut water: ResMut<WaterSettings>,
+    flood_stats: Res<FloodStats>,
+    city: Res<City>,
+    terrain: Option<Res<Terrain>>,
     keyboard: Res<Input<KeyCode>>,
 ) {
+    // Only write back real changes, so systems can rely on change detection
+    let mut new_settings = settings.clone();
+    let mut new_exaggeration = *exaggeration;
+    let mut new_scheme = scheme.clone();
+    let mut new_sun = sun.clone();
+    let mut new_water = water.clone();
     egui::Window::new("Controls").show(ctx.ctx_mut(), |ui| {
-        // TODO This probably stops both
-        if ui.button("Pause/resume height scaling").clicked()
-            || keyboard.just_pressed(KeyCode::Space)
-        {
-            for mut x in &mut query {
-                if x.state == AnimatorState::Playing {
-                    x.stop();
-                } else {
-                    x.state = AnimatorState::Playing;
-                }
-            }
-        }
+        ui.add(
+            egui::Slider::new(&mut new_exaggeration.0, 0.1..=10.0).text("vertical exaggeration"),
+        );
+        animation::controls(ui, &mut new_settings);
+        colours::controls(ui, &mut new_scheme, &legend);
+        sun::controls(ui, &mut new_sun);
+        let max_level = water::max_level(&city, terrain.as_deref(), exaggeration.0);
+        water::controls(ui, &mut new_water, &flood_stats, max_level);
     });
-}
-
-fn camera_controls(mut events: EventWriter<ControlEvent>, keyboard: Res<Input<KeyCode>>) {
-    let translate_speed = 15.0;
-    let rotate_speed = 2.0;
-
-    if keyboard.pressed(KeyCode::Up) {
-        events.send(ControlEvent::TranslateEye(translate_speed * Vec3::Z));
-    }
-    if keyboard.pressed(KeyCode::Down) {
-        events.send(ControlEvent::TranslateEye(translate_speed * -Vec3::Z));
-    }
-
-    let translate = keyboard.pressed(KeyCode::LShift);
-    for (key, translate_dir, rotate_dir) in [
-        (KeyCode::Left, Vec3::X, -Vec2::X),
-        (KeyCode::Right, -Vec3::X, Vec2::X),
-        (KeyCode::A, -Vec3::Y, Vec2::Y),
-        (KeyCode::Q, Vec3::Y, -Vec2::Y),
-    ] {
-        if keyboard.pressed(key) {
-            if translate {
-                events.send(ControlEvent::TranslateEye(translate_speed * translate_dir));
-            } else {
-                events.send(ControlEvent::Rotate(rotate_speed * rotate_dir));
-            }
-        }
+    if new_water != *water {
+        *water = new_water;
+    }
+    if new_sun != *sun {
+        *sun = new_sun;
+    }
+    if new_scheme != *scheme {
+        *scheme = new_scheme;
+    }
+    if new_exaggeration != *exaggeration {
+        *exaggeration = new_exaggeration;
+    }
+    if keyboard.just_pressed(KeyCode::Space) {
+        new_settings.height.playing = !new_settings.height.playing;
+    }
+    if new_settings != *settings {
+        *settings = new_settings;
     }
 }
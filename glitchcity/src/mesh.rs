@@ -1,39 +1,105 @@
-use anyhow::Result;
+use anyhow::{bail, Result};
 use bevy::prelude::{Mesh, Vec3};
 use bevy::render::mesh::{Indices, VertexAttributeValues};
 use bevy::render::render_resource::PrimitiveTopology;
 use bevy_earcutr::{EarcutrInput, PolygonMeshBuilder};
+use common::projection::Projection;
 use geo::{
-    BoundingRect, CoordsIter, Geometry, GeometryCollection, HaversineDistance, LineString,
-    MapCoordsInPlace, Point, Polygon,
+    BoundingRect, Coord, CoordsIter, Geometry, LineString, MapCoordsInPlace, MultiPolygon, Polygon,
 };
-use geojson::GeoJson;
+use geojson::{feature::Id, Feature, GeoJson};
 
-/// Load polygons from a GeoJSON file and transform to Mercator
-pub fn load_polygons(path: &str) -> Result<Vec<Polygon>> {
+use crate::buildings::Building;
+
+/// Geometry lives in the XY plane with Y pointing north. Bevy is Y-up, with -Z being forward, so
+/// north becomes -Z.
+pub fn to_world(c: Coord, y: f32) -> Vec3 {
+    Vec3::new(c.x as f32, y, -c.y as f32)
+}
+
+/// Load polygons from a GeoJSON file, in meters from their center
+pub fn load_polygons(path: &str) -> Result<(Vec<Building>, Projection)> {
     let geojson = std::fs::read_to_string(path)?.parse::<GeoJson>()?;
-    let mut collection: GeometryCollection<f64> = geojson::quick_collection(&geojson)?;
+    let features = match geojson {
+        GeoJson::FeatureCollection(collection) => collection.features,
+        GeoJson::Feature(feature) => vec![feature],
+        GeoJson::Geometry(geometry) => vec![Feature::from(geometry)],
+    };
 
-    // Filter out non-polygons
-    collection
-        .0
-        .retain(|geom| matches!(geom, Geometry::Polygon(_)));
+    // Filter out non-polygons, but remember which feature each came from. Each part of a
+    // multipolygon becomes its own building.
+    let mut buildings = Vec::new();
+    let mut degenerate = 0;
+    for (idx, feature) in features.into_iter().enumerate() {
+        let geometry = if let Some(ref geometry) = feature.geometry {
+            Geometry::try_from(geometry.clone())?
+        } else {
+            continue;
+        };
+        let polygons = match geometry {
+            Geometry::Polygon(polygon) => vec![polygon],
+            Geometry::MultiPolygon(multi) => multi.0,
+            _ => continue,
+        };
+        // Without a closed ring, there's no centroid to build around or anything to triangulate
+        let parts = polygons.len();
+        let polygons: Vec<Polygon> = polygons
+            .into_iter()
+            .filter(|polygon| polygon.exterior().0.len() >= 4)
+            .collect();
+        degenerate += parts - polygons.len();
+        let name = feature_name(&feature, idx);
+        let properties = feature.properties.unwrap_or_default();
+        for (part, polygon) in polygons.into_iter().enumerate() {
+            buildings.push(Building {
+                polygon,
+                // Keep names unique
+                name: if parts == 1 {
+                    name.clone()
+                } else {
+                    format!("{name}-{part}")
+                },
+                properties: properties.clone(),
+                height: 0.0,
+                base: 0.0,
+            });
+        }
+    }
+    if degenerate > 0 {
+        println!("Warning: skipping {degenerate} polygons with fewer than 3 corners");
+    }
+    if buildings.is_empty() {
+        bail!("{path} doesn't contain any polygons");
+    }
 
-    let top_left: Point = collection.bounding_rect().unwrap().min().into();
+    let bbox = MultiPolygon::new(buildings.iter().map(|b| b.polygon.clone()).collect())
+        .bounding_rect()
+        .unwrap();
+    let projection = Projection::centered(bbox);
 
-    collection.map_coords_in_place(|c| {
-        let x = Point::new(c.x, top_left.y()).haversine_distance(&top_left);
-        let y = Point::new(top_left.x(), c.y).haversine_distance(&top_left);
-        (x, y).into()
-    });
+    for building in &mut buildings {
+        building
+            .polygon
+            .map_coords_in_place(|c| projection.to_meters(c));
+    }
+    Ok((buildings, projection))
+}
 
-    let mut polygons = Vec::new();
-    for geom in collection {
-        if let Geometry::Polygon(polygon) = geom {
-            polygons.push(polygon);
+// Prefer the OSM ID when the GeoJSON came from Overpass, then the feature ID
+fn feature_name(feature: &Feature, idx: usize) -> String {
+    for key in ["@id", "id"] {
+        if let Some(value) = feature.property(key) {
+            if let Some(x) = value.as_str() {
+                return x.to_string();
+            }
+            return value.to_string();
         }
     }
-    Ok(polygons)
+    match feature.id {
+        Some(Id::String(ref x)) => x.clone(),
+        Some(Id::Number(ref x)) => x.to_string(),
+        None => format!("building{idx}"),
+    }
 }
 
 // TODO How clever is compute_flat_normals?
@@ -61,25 +127,17 @@ impl MeshBuilder {
         self.indices.extend([i1, i2, i3]);
     }
 
+    // The triangles are wound counter-clockwise when viewed from the side the normal points to,
+    // regardless of the order of the positions
     pub fn add_quad(&mut self, positions: [Vec3; 4], normal: Vec3) {
-        let c1 = self.add_vertex(Vertex {
-            pos: positions[0],
-            normal,
-        });
-        let c2 = self.add_vertex(Vertex {
-            pos: positions[1],
-            normal,
-        });
-        let c3 = self.add_vertex(Vertex {
-            pos: positions[2],
-            normal,
-        });
-        let c4 = self.add_vertex(Vertex {
-            pos: positions[3],
-            normal,
-        });
-        self.add_triangle(c1, c2, c3);
-        self.add_triangle(c3, c4, c1);
+        let [c1, c2, c3, c4] = positions.map(|pos| self.add_vertex(Vertex { pos, normal }));
+        if facing(positions[0], positions[1], positions[2], normal) {
+            self.add_triangle(c1, c2, c3);
+            self.add_triangle(c3, c4, c1);
+        } else {
+            self.add_triangle(c3, c2, c1);
+            self.add_triangle(c1, c4, c3);
+        }
     }
 
     // Adds a polygon in the XZ plane
@@ -88,7 +146,8 @@ impl MeshBuilder {
         builder.add_earcutr_input(polygon_to_earcutr_input(polygon));
         let mesh = builder.build().unwrap();
 
-        // Extract positions from the mesh. It'll use XY and ignore Z, but we use XZ
+        // Extract positions from the mesh. It'll use XY and ignore Z, but we use XZ, with north
+        // being -Z. Same as to_world.
         let offset = self.vertices.len() as u32;
         if let Some(VertexAttributeValues::Float32x3(positions)) =
             mesh.attribute(Mesh::ATTRIBUTE_POSITION)
@@ -98,23 +157,38 @@ impl MeshBuilder {
                     pos: Vec3 {
                         x: pos[0],
                         y,
-                        z: pos[1],
+                        z: -pos[1],
                     },
-                    normal: normal.clone(),
+                    normal,
                 });
             }
         } else {
             unreachable!()
         }
         if let Some(Indices::U32(indices)) = mesh.indices() {
-            for idx in indices {
-                self.indices.push(offset + idx);
+            // earcutr doesn't promise any winding order, so fix each triangle to face the normal
+            for triangle in indices.chunks(3) {
+                let [i1, i2, i3] = [0, 1, 2].map(|i| offset + triangle[i]);
+                let [p1, p2, p3] = [i1, i2, i3].map(|i| self.vertices[i as usize].pos);
+                if facing(p1, p2, p3, normal) {
+                    self.add_triangle(i1, i2, i3);
+                } else {
+                    self.add_triangle(i3, i2, i1);
+                }
             }
         } else {
             unreachable!()
         }
     }
 
+    pub fn vertices(&self) -> &[Vertex] {
+        &self.vertices
+    }
+
+    pub fn indices(&self) -> &[u32] {
+        &self.indices
+    }
+
     pub fn build(self) -> Mesh {
         let mut mesh = Mesh::new(PrimitiveTopology::TriangleList);
         mesh.set_indices(Some(Indices::U32(self.indices)));
@@ -133,6 +207,11 @@ impl MeshBuilder {
     }
 }
 
+// Does the triangle p1, p2, p3 wind counter-clockwise around the normal?
+fn facing(p1: Vec3, p2: Vec3, p3: Vec3, normal: Vec3) -> bool {
+    (p2 - p1).cross(p3 - p1).dot(normal) >= 0.0
+}
+
 pub struct Vertex {
     pub pos: Vec3,
     pub normal: Vec3,
@@ -164,3 +243,34 @@ fn flat_line_string_coords_2(line_string: &LineString, vertices: &mut Vec<f64>)
         vertices.push(coord.y);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn degenerate_polygons_are_skipped() {
+        let path = std::env::temp_dir().join("glitchcity_degenerate_polygons_are_skipped.geojson");
+        let path = path.to_str().unwrap();
+        let square = "[[[0, 0], [0.001, 0], [0.001, 0.001], [0, 0.001], [0, 0]]]";
+        std::fs::write(
+            path,
+            format!(
+                r#"{{"type": "FeatureCollection", "features": [
+                    {{"type": "Feature", "id": "empty", "properties": {{}},
+                      "geometry": {{"type": "Polygon", "coordinates": [[]]}}}},
+                    {{"type": "Feature", "id": "square", "properties": {{}},
+                      "geometry": {{"type": "Polygon", "coordinates": {square}}}}},
+                    {{"type": "Feature", "id": "pair", "properties": {{}},
+                      "geometry": {{"type": "MultiPolygon", "coordinates": [{square}, [[]]]}}}}
+                ]}}"#
+            ),
+        )
+        .unwrap();
+        let result = load_polygons(path);
+        std::fs::remove_file(path).unwrap();
+
+        let names: Vec<String> = result.unwrap().0.into_iter().map(|b| b.name).collect();
+        assert_eq!(names, vec!["square", "pair-0"]);
+    }
+}
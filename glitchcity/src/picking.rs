@@ -0,0 +1,91 @@
+use bevy::math::Ray;
+use bevy::prelude::{
+    Assets, Camera, Entity, GlobalTransform, Handle, Mesh, Query, Vec3, Windows, With,
+};
+use bevy::render::mesh::{Indices, VertexAttributeValues};
+
+use crate::colours::BuildingIdx;
+
+/// The ray from the camera through the cursor, if it's over the window
+pub fn cursor_ray(windows: &Windows, cameras: &Query<(&Camera, &GlobalTransform)>) -> Option<Ray> {
+    let cursor = windows.get_primary()?.cursor_position()?;
+    cameras
+        .iter()
+        .find_map(|(camera, transform)| camera.viewport_to_world(transform, cursor))
+}
+
+/// Where the ray meets the ground, at y = 0
+pub fn ground_point(ray: Ray) -> Option<Vec3> {
+    if ray.direction.y >= 0.0 {
+        return None;
+    }
+    let t = -ray.origin.y / ray.direction.y;
+    Some(ray.origin + t * ray.direction)
+}
+
+/// The closest building hit by the ray, and where. Tests the actual meshes, so spinning and
+/// stretched buildings are picked as they look.
+pub fn pick_building(
+    ray: Ray,
+    buildings: &Query<(Entity, &Handle<Mesh>, &GlobalTransform), With<BuildingIdx>>,
+    meshes: &Assets<Mesh>,
+) -> Option<(Entity, Vec3)> {
+    let mut best: Option<(Entity, f32)> = None;
+    for (entity, handle, transform) in buildings {
+        let mesh = if let Some(mesh) = meshes.get(handle) {
+            mesh
+        } else {
+            continue;
+        };
+        // Work in the mesh's space. Without normalizing the direction, distances along the ray
+        // stay comparable between buildings.
+        let inverse = transform.compute_matrix().inverse();
+        let origin = inverse.transform_point3(ray.origin);
+        let direction = inverse.transform_vector3(ray.direction);
+
+        let positions = if let Some(VertexAttributeValues::Float32x3(positions)) =
+            mesh.attribute(Mesh::ATTRIBUTE_POSITION)
+        {
+            positions
+        } else {
+            continue;
+        };
+        let indices = if let Some(Indices::U32(indices)) = mesh.indices() {
+            indices
+        } else {
+            continue;
+        };
+        for triangle in indices.chunks(3) {
+            let [p1, p2, p3] = [0, 1, 2].map(|i| Vec3::from(positions[triangle[i] as usize]));
+            if let Some(t) = ray_triangle(origin, direction, p1, p2, p3) {
+                if best.is_none_or(|(_, best_t)| t < best_t) {
+                    best = Some((entity, t));
+                }
+            }
+        }
+    }
+    best.map(|(entity, t)| (entity, ray.origin + t * ray.direction))
+}
+
+// Möller–Trumbore, ignoring which way the triangle faces
+fn ray_triangle(origin: Vec3, direction: Vec3, p1: Vec3, p2: Vec3, p3: Vec3) -> Option<f32> {
+    let edge1 = p2 - p1;
+    let edge2 = p3 - p1;
+    let h = direction.cross(edge2);
+    let det = edge1.dot(h);
+    if det.abs() < f32::EPSILON {
+        return None;
+    }
+    let s = origin - p1;
+    let u = s.dot(h) / det;
+    if !(0.0..=1.0).contains(&u) {
+        return None;
+    }
+    let q = s.cross(edge1);
+    let v = direction.dot(q) / det;
+    if v < 0.0 || u + v > 1.0 {
+        return None;
+    }
+    let t = edge2.dot(q) / det;
+    (t > 0.0).then_some(t)
+}
@@ -0,0 +1,351 @@
+use std::collections::{BTreeMap, HashMap};
+use std::io::Write;
+
+use anyhow::{bail, Result};
+use bevy::prelude::Vec3;
+use geo::{BooleanOps, BoundingRect, MultiPolygon, Polygon, Rect};
+
+use crate::buildings::{self, Building};
+use crate::mesh::MeshBuilder;
+
+/// How to turn the city into something physical
+pub struct PrintSettings {
+    /// Only keep buildings in this area, clipping the ones crossing the edge. In meters, like the
+    /// buildings. If missing, use everything.
+    pub area: Option<Rect>,
+    /// 1:scale, so 5000 means 1mm on the print is 5m in reality
+    pub scale: f64,
+    /// Multiply building heights by this
+    pub vertical_exaggeration: f32,
+    /// Thickness of the base plate, in millimeters
+    pub base_mm: f32,
+}
+
+/// A triangle mesh in millimeters, Z-up, as expected by slicers. Vertices are welded.
+pub struct PrintMesh {
+    positions: Vec<Vec3>,
+    /// Each position, rounded for welding
+    keys: Vec<[i64; 3]>,
+    triangles: Vec<[u32; 3]>,
+}
+
+/// The buildings are merged into terraces. For every distinct height, the union of all footprints
+/// at least that tall is extruded up from the level below, so touching buildings share walls
+/// instead of overlapping. Once vertices are welded and edges are split where corners land on
+/// them, everything is one watertight, manifold solid.
+pub fn build(buildings: &[Building], settings: &PrintSettings) -> Result<PrintMesh> {
+    let mm_per_meter = (1000.0 / settings.scale) as f32;
+    let base_meters = settings.base_mm / mm_per_meter;
+
+    // Each footprint, with the elevation of its floor and its height
+    let mut polygons: Vec<(Polygon, f32, f32)> = Vec::new();
+    for building in buildings {
+        let height = building.height * settings.vertical_exaggeration;
+        if let Some(area) = settings.area {
+            for polygon in building.polygon.intersection(&area.to_polygon()) {
+                polygons.push((polygon, building.base, height));
+            }
+        } else {
+            polygons.push((building.polygon.clone(), building.base, height));
+        }
+    }
+    if polygons.is_empty() {
+        bail!("No buildings in the selected area");
+    }
+    // With terrain, the plate is at the lowest floor. Buildings further up a slope stand on
+    // taller columns, so their roofs are still at the right elevation.
+    let lowest = polygons
+        .iter()
+        .map(|(_, base, _)| *base)
+        .reduce(f32::min)
+        .unwrap();
+    let area = settings.area.unwrap_or_else(|| {
+        MultiPolygon::new(polygons.iter().map(|(p, _, _)| p.clone()).collect())
+            .bounding_rect()
+            .unwrap()
+    });
+
+    // Group footprints by height, rounded to how precisely vertices are welded. Otherwise nearly
+    // equal heights would make walls that collapse.
+    let mut levels: BTreeMap<i64, Vec<Polygon>> = BTreeMap::new();
+    for (polygon, base, height) in polygons {
+        let key = ((base - lowest + height) * mm_per_meter * WELD_PER_MM).round() as i64;
+        if key > 0 {
+            levels.entry(key).or_default().push(polygon);
+        }
+    }
+
+    // Each tier is the area covered at least up to its height, from the base plate up
+    let mut tiers = Vec::new();
+    let mut covered = MultiPolygon::new(Vec::new());
+    for (key, polygons) in levels.into_iter().rev() {
+        covered = covered.union(&union_all(polygons));
+        let height = key as f32 / WELD_PER_MM / mm_per_meter;
+        tiers.push((base_meters + height, covered.clone()));
+    }
+    tiers.push((base_meters, MultiPolygon::new(vec![area.to_polygon()])));
+    tiers.reverse();
+
+    let mut builder = MeshBuilder::new();
+    builder.triangulate_polygon(&area.to_polygon(), 0.0, Vec3::NEG_Y);
+    let mut below = 0.0;
+    for (idx, (y, tier)) in tiers.iter().enumerate() {
+        // The roof is whatever the next tier up doesn't cover
+        let roof = match tiers.get(idx + 1) {
+            Some((_, above)) => tier.difference(above),
+            None => tier.clone(),
+        };
+        for polygon in tier {
+            buildings::add_walls(polygon, below, *y, &mut builder);
+        }
+        for polygon in &roof {
+            builder.triangulate_polygon(polygon, *y, Vec3::Y);
+        }
+        below = *y;
+    }
+
+    let mut mesh = PrintMesh::weld(&builder, mm_per_meter);
+    mesh.split_t_junctions();
+
+    // Put the corner of the base plate at the origin
+    let offset = Vec3::new(area.min().x as f32, area.min().y as f32, 0.0) * mm_per_meter;
+    for pos in &mut mesh.positions {
+        *pos -= offset;
+    }
+
+    Ok(mesh)
+}
+
+// Vertices are welded to a thousandth of a millimeter
+const WELD_PER_MM: f32 = 1000.0;
+
+// Unions in pairs, so the pieces stay small
+fn union_all(polygons: Vec<Polygon>) -> MultiPolygon {
+    let mut pieces: Vec<MultiPolygon> = polygons
+        .into_iter()
+        .map(|p| MultiPolygon::new(vec![p]))
+        .collect();
+    while pieces.len() > 1 {
+        pieces = pieces
+            .chunks(2)
+            .map(|pair| match pair {
+                [a, b] => a.union(b),
+                [a] => a.clone(),
+                _ => unreachable!(),
+            })
+            .collect();
+    }
+    pieces
+        .pop()
+        .unwrap_or_else(|| MultiPolygon::new(Vec::new()))
+}
+
+impl PrintMesh {
+    // The builder is Y-up with -Z being north, so rotate to Z-up
+    fn weld(builder: &MeshBuilder, mm_per_meter: f32) -> Self {
+        let mut mesh = Self {
+            positions: Vec::new(),
+            keys: Vec::new(),
+            triangles: Vec::new(),
+        };
+        let mut welded: HashMap<[i64; 3], u32> = HashMap::new();
+        let mut lookup = Vec::new();
+        for vert in builder.vertices() {
+            let pos = Vec3::new(vert.pos.x, -vert.pos.z, vert.pos.y) * mm_per_meter;
+            let key = pos.to_array().map(|x| (x * WELD_PER_MM).round() as i64);
+            let idx = *welded.entry(key).or_insert_with(|| {
+                mesh.positions.push(pos);
+                mesh.keys.push(key);
+                (mesh.positions.len() - 1) as u32
+            });
+            lookup.push(idx);
+        }
+
+        for triangle in builder.indices().chunks(3) {
+            let [i1, i2, i3] = [triangle[0], triangle[1], triangle[2]].map(|i| lookup[i as usize]);
+            // Welding can collapse slivers
+            if i1 != i2 && i2 != i3 && i1 != i3 {
+                mesh.triangles.push([i1, i2, i3]);
+            }
+        }
+        mesh
+    }
+
+    // Neighbouring faces don't always have corners in the same places. The top of a wall is one
+    // line, but the roof beside it has a corner wherever a taller building starts. Split every
+    // flat edge at the corners lying on it, so each edge is shared by exactly two triangles.
+    fn split_t_junctions(&mut self) {
+        // Corners at each height, sorted by X
+        let mut by_z: HashMap<i64, Vec<u32>> = HashMap::new();
+        for (idx, key) in self.keys.iter().enumerate() {
+            by_z.entry(key[2]).or_default().push(idx as u32);
+        }
+        for corners in by_z.values_mut() {
+            corners.sort_by_key(|i| self.keys[*i as usize][0]);
+        }
+
+        let mut todo = std::mem::take(&mut self.triangles);
+        while let Some(triangle) = todo.pop() {
+            let split = (0..3).find_map(|i| {
+                let (a, b) = (triangle[i], triangle[(i + 1) % 3]);
+                let corner = self.corner_on_edge(&by_z, a, b)?;
+                Some((i, corner))
+            });
+            if let Some((i, corner)) = split {
+                let [a, b, c] = [0, 1, 2].map(|j| triangle[(i + j) % 3]);
+                todo.push([a, corner, c]);
+                todo.push([corner, b, c]);
+            } else {
+                self.triangles.push(triangle);
+            }
+        }
+    }
+
+    // Finds a corner strictly inside a flat edge, allowing for rounding
+    fn corner_on_edge(&self, by_z: &HashMap<i64, Vec<u32>>, a: u32, b: u32) -> Option<u32> {
+        let [ax, ay, az] = self.keys[a as usize];
+        let [bx, by, bz] = self.keys[b as usize];
+        if az != bz {
+            return None;
+        }
+        let corners = by_z.get(&az)?;
+        let (dx, dy) = ((bx - ax) as f64, (by - ay) as f64);
+        let length_squared = dx * dx + dy * dy;
+        let (min_x, max_x) = (ax.min(bx) - 1, ax.max(bx) + 1);
+        let start = corners.partition_point(|i| self.keys[*i as usize][0] < min_x);
+        corners[start..]
+            .iter()
+            .take_while(|i| self.keys[**i as usize][0] <= max_x)
+            .copied()
+            .find(|i| {
+                if *i == a || *i == b {
+                    return false;
+                }
+                let [px, py, _] = self.keys[*i as usize];
+                let (ex, ey) = ((px - ax) as f64, (py - ay) as f64);
+                let along = ex * dx + ey * dy;
+                let across = (ex * dy - ey * dx).abs() / length_squared.sqrt();
+                along > 0.0 && along < length_squared && across <= 1.5
+            })
+    }
+
+    /// Counts the edges that aren't used by exactly two triangles in opposite directions. Zero
+    /// means the mesh is watertight and manifold.
+    pub fn count_bad_edges(&self) -> usize {
+        let mut edges: HashMap<(u32, u32), usize> = HashMap::new();
+        for [i1, i2, i3] in &self.triangles {
+            for edge in [(*i1, *i2), (*i2, *i3), (*i3, *i1)] {
+                *edges.entry(edge).or_insert(0) += 1;
+            }
+        }
+        edges
+            .iter()
+            .filter(|((a, b), count)| **count != 1 || edges.get(&(*b, *a)) != Some(&1))
+            .count()
+    }
+
+    fn normal(&self, triangle: &[u32; 3]) -> Vec3 {
+        let [p1, p2, p3] = triangle.map(|i| self.positions[i as usize]);
+        (p2 - p1).cross(p3 - p1).normalize_or_zero()
+    }
+
+    pub fn write_stl(&self, path: &str) -> Result<()> {
+        let mut file = std::io::BufWriter::new(std::fs::File::create(path)?);
+        let mut header = [0_u8; 80];
+        let label = b"glitchcity";
+        header[..label.len()].copy_from_slice(label);
+        file.write_all(&header)?;
+        file.write_all(&(self.triangles.len() as u32).to_le_bytes())?;
+        for triangle in &self.triangles {
+            let normal = self.normal(triangle);
+            for pt in std::iter::once(normal).chain(triangle.map(|i| self.positions[i as usize])) {
+                for value in pt.to_array() {
+                    file.write_all(&value.to_le_bytes())?;
+                }
+            }
+            // Attribute byte count
+            file.write_all(&[0, 0])?;
+        }
+        file.flush()?;
+        println!("Wrote {} triangles to {path}", self.triangles.len());
+        Ok(())
+    }
+
+    pub fn write_obj(&self, path: &str) -> Result<()> {
+        let mut file = std::io::BufWriter::new(std::fs::File::create(path)?);
+        writeln!(file, "# glitchcity, units are millimeters")?;
+        for pos in &self.positions {
+            writeln!(file, "v {} {} {}", pos.x, pos.y, pos.z)?;
+        }
+        for [i1, i2, i3] in &self.triangles {
+            // OBJ is 1-indexed
+            writeln!(file, "f {} {} {}", i1 + 1, i2 + 1, i3 + 1)?;
+        }
+        file.flush()?;
+        println!("Wrote {} triangles to {path}", self.triangles.len());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use geojson::JsonObject;
+
+    use super::*;
+
+    fn building(x1: f64, y1: f64, x2: f64, y2: f64, height: f32) -> Building {
+        Building {
+            polygon: Rect::new((x1, y1), (x2, y2)).to_polygon(),
+            name: String::new(),
+            properties: JsonObject::new(),
+            height,
+            base: 0.0,
+        }
+    }
+
+    fn settings(area: Option<Rect>) -> PrintSettings {
+        PrintSettings {
+            area,
+            scale: 1000.0,
+            vertical_exaggeration: 1.0,
+            base_mm: 2.0,
+        }
+    }
+
+    #[test]
+    fn adjacent_buildings_are_manifold() {
+        // Part of a wall is shared, and the heights differ
+        let buildings = [
+            building(0.0, 0.0, 10.0, 10.0, 20.0),
+            building(10.0, 5.0, 20.0, 15.0, 12.0),
+        ];
+        let mesh = build(&buildings, &settings(None)).unwrap();
+        assert_eq!(mesh.count_bad_edges(), 0);
+    }
+
+    #[test]
+    fn overlapping_and_clipped_buildings_are_manifold() {
+        let buildings = [
+            building(0.0, 0.0, 10.0, 10.0, 20.0),
+            building(5.0, 5.0, 15.0, 15.0, 20.0),
+            building(12.0, 0.0, 30.0, 4.0, 8.0),
+            building(20.0, 20.0, 25.0, 25.0, 0.0),
+        ];
+        let area = Rect::new((-5.0, -5.0), (25.0, 25.0));
+        let mesh = build(&buildings, &settings(Some(area))).unwrap();
+        assert_eq!(mesh.count_bad_edges(), 0);
+    }
+
+    #[test]
+    fn buildings_stand_on_terrain() {
+        let mut uphill = building(10.0, 0.0, 20.0, 10.0, 10.0);
+        uphill.base = 105.0;
+        let mut downhill = building(0.0, 0.0, 10.0, 10.0, 10.0);
+        downhill.base = 100.0;
+        let mesh = build(&[uphill, downhill], &settings(None)).unwrap();
+        assert_eq!(mesh.count_bad_edges(), 0);
+        // 1m is 1mm, on top of the 2mm plate
+        let top = mesh.positions.iter().map(|p| p.z).reduce(f32::max).unwrap();
+        assert!((top - 17.0).abs() < 0.01);
+    }
+}
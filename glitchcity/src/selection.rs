@@ -0,0 +1,128 @@
+use bevy::prelude::{
+    Assets, Camera, Entity, GlobalTransform, Handle, Input, Local, Mesh, MouseButton, Query, Res,
+    ResMut, Resource, Vec2, Windows, With,
+};
+use bevy_egui::{egui, EguiContext};
+use geo::{Area, EuclideanLength};
+
+use crate::animation::BuildingAnimation;
+use crate::buildings::{City, VerticalExaggeration};
+use crate::colours::BuildingIdx;
+use crate::picking;
+
+// The cursor can wander this many pixels during a click. Further, and it's a drag.
+const CLICK_TOLERANCE_PIXELS: f32 = 5.0;
+
+/// The building whose properties are shown
+#[derive(Resource, Default, PartialEq)]
+pub struct Selection(pub Option<usize>);
+
+/// Clicking a building selects it, and clicking nothing clears the selection. Dragging to orbit
+/// doesn't count.
+#[allow(clippy::too_many_arguments)]
+pub fn select_on_click(
+    mut ctx: ResMut<EguiContext>,
+    mut pressed_at: Local<Option<Vec2>>,
+    mut selection: ResMut<Selection>,
+    mouse: Res<Input<MouseButton>>,
+    windows: Res<Windows>,
+    cameras: Query<(&Camera, &GlobalTransform)>,
+    buildings: Query<(Entity, &Handle<Mesh>, &GlobalTransform), With<BuildingIdx>>,
+    indices: Query<&BuildingIdx>,
+    meshes: Res<Assets<Mesh>>,
+) {
+    let cursor = windows.get_primary().and_then(|w| w.cursor_position());
+    if mouse.just_pressed(MouseButton::Left) {
+        *pressed_at = if ctx.ctx_mut().wants_pointer_input() {
+            None
+        } else {
+            cursor
+        };
+    }
+    if !mouse.just_released(MouseButton::Left) {
+        return;
+    }
+    match (pressed_at.take(), cursor) {
+        (Some(start), Some(end)) if start.distance(end) <= CLICK_TOLERANCE_PIXELS => {}
+        _ => return,
+    }
+
+    let hit = picking::cursor_ray(&windows, &cameras)
+        .and_then(|ray| picking::pick_building(ray, &buildings, &meshes))
+        .and_then(|(entity, _)| indices.get(entity).ok());
+    let new_selection = Selection(hit.map(|idx| idx.0));
+    if new_selection != *selection {
+        *selection = new_selection;
+    }
+}
+
+pub fn highlight_selection(
+    selection: Res<Selection>,
+    mut query: Query<(&BuildingIdx, &mut BuildingAnimation)>,
+) {
+    if !selection.is_changed() {
+        return;
+    }
+    for (idx, mut animation) in &mut query {
+        let selected = selection.0 == Some(idx.0);
+        // Avoid triggering change detection, which would upload the material again
+        if animation.selected != selected {
+            animation.selected = selected;
+        }
+    }
+}
+
+pub fn inspector(
+    mut ctx: ResMut<EguiContext>,
+    mut selection: ResMut<Selection>,
+    city: Res<City>,
+    exaggeration: Res<VerticalExaggeration>,
+) {
+    let building = if let Some(idx) = selection.0 {
+        &city.buildings[idx]
+    } else {
+        return;
+    };
+    let mut clear = false;
+    egui::Window::new("Selected building").show(ctx.ctx_mut(), |ui| {
+        ui.horizontal(|ui| {
+            ui.heading(&building.name);
+            clear = ui.button("Clear").clicked();
+        });
+        let perimeter: f64 = std::iter::once(building.polygon.exterior())
+            .chain(building.polygon.interiors())
+            .map(|ring| ring.euclidean_length())
+            .sum();
+        ui.label(format!(
+            "{:.0} m² footprint, {perimeter:.0} m perimeter",
+            building.polygon.unsigned_area()
+        ));
+        ui.label(format!(
+            "{:.1} m tall, shown {:.1} m tall",
+            building.height,
+            building.height * exaggeration.0
+        ));
+
+        ui.separator();
+        if building.properties.is_empty() {
+            ui.label("No properties");
+        }
+        egui::ScrollArea::vertical()
+            .max_height(300.0)
+            .show(ui, |ui| {
+                egui::Grid::new("properties").striped(true).show(ui, |ui| {
+                    for (key, value) in &building.properties {
+                        ui.label(key);
+                        match value.as_str() {
+                            Some(x) => ui.label(x),
+                            None => ui.label(value.to_string()),
+                        };
+                        ui.end_row();
+                    }
+                });
+            });
+    });
+    if clear {
+        selection.0 = None;
+    }
+}
@@ -0,0 +1,221 @@
+use std::f32::consts::PI;
+
+use bevy::prelude::{
+    Component, DirectionalLight, Query, Res, ResMut, Resource, Time, Transform, Vec3, With,
+};
+use bevy_egui::egui;
+
+// On a clear day, with the sun overhead
+const FULL_SUN_LUX: f32 = 20000.0;
+
+const DAYS_PER_MONTH: [u32; 12] = [31, 28, 31, 30, 31, 30, 31, 31, 30, 31, 30, 31];
+const MONTH_NAMES: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+/// Marks the directional light playing the sun
+#[derive(Component)]
+pub struct Sun;
+
+#[derive(Resource, Clone, PartialEq)]
+pub struct SunSettings {
+    /// Where the city is, in degrees
+    pub lon: f64,
+    pub lat: f64,
+    /// From 1 to 12
+    pub month: u32,
+    /// From 1 to the length of the month. Leap years are ignored.
+    pub day: u32,
+    /// Local clock time, in hours from midnight
+    pub hour: f32,
+    /// Hours ahead of UTC for the local clock
+    pub utc_offset: f32,
+    /// Sweep through the day
+    pub animating: bool,
+    /// Simulated hours per real second, while animating
+    pub hours_per_second: f32,
+}
+
+impl SunSettings {
+    /// The summer solstice, in the afternoon
+    pub fn new(lon: f64, lat: f64) -> Self {
+        Self {
+            lon,
+            lat,
+            month: 6,
+            day: 21,
+            hour: 15.0,
+            // Just a guess, ignoring political time zones and daylight saving
+            utc_offset: (lon / 15.0).round() as f32,
+            animating: false,
+            hours_per_second: 1.0,
+        }
+    }
+
+    fn day_of_year(&self) -> u32 {
+        DAYS_PER_MONTH[..(self.month - 1) as usize]
+            .iter()
+            .sum::<u32>()
+            + self.day
+    }
+
+    /// Returns (azimuth, elevation) of the sun in degrees. Azimuth is clockwise from north.
+    /// Follows NOAA's general solar position calculations.
+    pub fn solar_position(&self) -> (f32, f32) {
+        let utc_hour = self.hour - self.utc_offset;
+        // The fractional year, in radians
+        let gamma = 2.0 * PI / 365.0 * (self.day_of_year() as f32 - 1.0 + (utc_hour - 12.0) / 24.0);
+        // In minutes
+        let equation_of_time = 229.18
+            * (0.000075 + 0.001868 * gamma.cos()
+                - 0.032077 * gamma.sin()
+                - 0.014615 * (2.0 * gamma).cos()
+                - 0.040849 * (2.0 * gamma).sin());
+        // In radians
+        let declination = 0.006918 - 0.399912 * gamma.cos() + 0.070257 * gamma.sin()
+            - 0.006758 * (2.0 * gamma).cos()
+            + 0.000907 * (2.0 * gamma).sin()
+            - 0.002697 * (3.0 * gamma).cos()
+            + 0.00148 * (3.0 * gamma).sin();
+
+        let true_solar_minutes = utc_hour * 60.0 + equation_of_time + 4.0 * self.lon as f32;
+        let hour_angle = (true_solar_minutes / 4.0 - 180.0).to_radians();
+        let lat = (self.lat as f32).to_radians();
+
+        let elevation = (lat.sin() * declination.sin()
+            + lat.cos() * declination.cos() * hour_angle.cos())
+        .clamp(-1.0, 1.0)
+        .asin();
+        // Measured from south, then turned around
+        let azimuth = hour_angle
+            .sin()
+            .atan2(hour_angle.cos() * lat.sin() - declination.tan() * lat.cos())
+            + PI;
+        (
+            azimuth.to_degrees().rem_euclid(360.0),
+            elevation.to_degrees(),
+        )
+    }
+}
+
+/// Advance the clock while animating
+pub fn animate_sun(time: Res<Time>, mut settings: ResMut<SunSettings>) {
+    if settings.animating {
+        settings.hour =
+            (settings.hour + settings.hours_per_second * time.delta_seconds()).rem_euclid(24.0);
+    }
+}
+
+pub fn update_sun(
+    settings: Res<SunSettings>,
+    mut query: Query<(&mut Transform, &mut DirectionalLight), With<Sun>>,
+) {
+    if !settings.is_changed() {
+        return;
+    }
+    let (azimuth, elevation) = settings.solar_position();
+    let (azimuth, elevation) = (azimuth.to_radians(), elevation.to_radians());
+    // East is +X and north is -Z
+    let towards_sun = Vec3::new(
+        elevation.cos() * azimuth.sin(),
+        elevation.sin(),
+        -elevation.cos() * azimuth.cos(),
+    );
+    for (mut transform, mut light) in &mut query {
+        // Shining from the sun towards the center. Below the horizon, it's night.
+        if elevation > 0.0 {
+            *transform = Transform::from_translation(towards_sun).looking_at(Vec3::ZERO, Vec3::Y);
+            light.illuminance = FULL_SUN_LUX * elevation.sin();
+        } else {
+            light.illuminance = 0.0;
+        }
+    }
+}
+
+pub fn controls(ui: &mut egui::Ui, settings: &mut SunSettings) {
+    ui.separator();
+    ui.horizontal(|ui| {
+        ui.heading("Sun");
+        ui.checkbox(&mut settings.animating, "animate the day");
+    });
+    ui.horizontal(|ui| {
+        egui::ComboBox::from_id_source("month")
+            .selected_text(MONTH_NAMES[(settings.month - 1) as usize])
+            .show_ui(ui, |ui| {
+                for (idx, name) in MONTH_NAMES.iter().enumerate() {
+                    ui.selectable_value(&mut settings.month, idx as u32 + 1, *name);
+                }
+            });
+        let days = DAYS_PER_MONTH[(settings.month - 1) as usize];
+        settings.day = settings.day.min(days);
+        ui.add(egui::Slider::new(&mut settings.day, 1..=days).text("day"));
+    });
+    let hour = settings.hour;
+    ui.add(
+        egui::Slider::new(&mut settings.hour, 0.0..=24.0).text(format!(
+            "{:02}:{:02}",
+            hour.floor() as u32 % 24,
+            (hour.fract() * 60.0) as u32
+        )),
+    );
+    ui.add(egui::Slider::new(&mut settings.utc_offset, -12.0..=14.0).text("hours from UTC"));
+    ui.add(egui::Slider::new(&mut settings.hours_per_second, 0.1..=5.0).text("hours per second"));
+
+    let (azimuth, elevation) = settings.solar_position();
+    ui.label(format!(
+        "Azimuth {azimuth:.0}°, elevation {elevation:.0}°{}",
+        if elevation <= 0.0 { " (night)" } else { "" }
+    ));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Greenwich, at noon UTC
+    fn london(month: u32, day: u32) -> SunSettings {
+        SunSettings {
+            month,
+            day,
+            hour: 12.0,
+            utc_offset: 0.0,
+            ..SunSettings::new(0.0, 51.48)
+        }
+    }
+
+    #[test]
+    fn noon_sun_is_due_south_at_the_right_height() {
+        // 90 degrees minus the latitude, plus or minus the tilt of the earth
+        let (azimuth, elevation) = london(6, 21).solar_position();
+        assert!((azimuth - 180.0).abs() < 2.0, "{azimuth}");
+        assert!((elevation - 61.96).abs() < 0.5, "{elevation}");
+
+        let (azimuth, elevation) = london(12, 21).solar_position();
+        assert!((azimuth - 180.0).abs() < 2.0, "{azimuth}");
+        assert!((elevation - 15.08).abs() < 0.5, "{elevation}");
+    }
+
+    #[test]
+    fn sun_rises_in_the_east_and_sets_below_the_horizon() {
+        let morning = SunSettings {
+            hour: 6.0,
+            ..london(3, 20)
+        };
+        let (azimuth, elevation) = morning.solar_position();
+        assert!((azimuth - 90.0).abs() < 5.0, "{azimuth}");
+        assert!(elevation.abs() < 2.0, "{elevation}");
+
+        let midnight = SunSettings {
+            hour: 0.0,
+            ..london(6, 21)
+        };
+        assert!(midnight.solar_position().1 < 0.0);
+    }
+
+    #[test]
+    fn day_of_year_counts_from_january_first() {
+        assert_eq!(london(1, 1).day_of_year(), 1);
+        assert_eq!(london(3, 1).day_of_year(), 60);
+        assert_eq!(london(12, 31).day_of_year(), 365);
+    }
+}
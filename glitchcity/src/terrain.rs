@@ -0,0 +1,370 @@
+use std::io::BufReader;
+
+use anyhow::{bail, Context, Result};
+use bevy::prelude::{Mesh, Resource, Vec3};
+use common::projection::Projection;
+use geo::{Coord, Polygon, Rect};
+use tiff::decoder::{Decoder, DecodingResult};
+use tiff::tags::Tag;
+
+use crate::mesh::{to_world, MeshBuilder, Vertex};
+
+// Bigger DEMs are sampled more coarsely, to keep the mesh reasonable
+const MAX_SAMPLES_PER_SIDE: usize = 512;
+
+/// A digital elevation model, as read from disk. It must be in WGS84, with cells in degrees.
+pub struct Dem {
+    ncols: usize,
+    nrows: usize,
+    /// The longitude of the center of the first column
+    x0: f64,
+    /// The latitude of the center of the first (northernmost) row
+    y0: f64,
+    /// Degrees between column centers
+    dx: f64,
+    /// Degrees between row centers, going south
+    dy: f64,
+    /// Row-major, north to south. NaN where there's no data.
+    values: Vec<f32>,
+}
+
+impl Dem {
+    /// Reads an ESRI ASCII grid (.asc) or a GeoTIFF (.tif)
+    pub fn load(path: &str) -> Result<Dem> {
+        let lower = path.to_lowercase();
+        let dem = if lower.ends_with(".tif") || lower.ends_with(".tiff") {
+            read_geotiff(path)
+        } else {
+            read_ascii_grid(path)
+        }
+        .with_context(|| format!("reading DEM {path}"))?;
+        if dem.values.len() != dem.ncols * dem.nrows {
+            bail!(
+                "{path} should have {} x {} values, but has {}",
+                dem.ncols,
+                dem.nrows,
+                dem.values.len()
+            );
+        }
+        Ok(dem)
+    }
+
+    fn get(&self, col: usize, row: usize) -> f32 {
+        self.values[row * self.ncols + col]
+    }
+}
+
+fn read_ascii_grid(path: &str) -> Result<Dem> {
+    let contents = std::fs::read_to_string(path)?;
+    let mut words = contents.split_whitespace().peekable();
+
+    let mut ncols = None;
+    let mut nrows = None;
+    let mut xll = None;
+    let mut yll = None;
+    // xllcorner vs xllcenter
+    let mut corner = true;
+    let mut dx = None;
+    let mut dy = None;
+    let mut nodata = None;
+    // The header is "key value" pairs, until the first number
+    while let Some(key) = words.next_if(|w| w.parse::<f64>().is_err()) {
+        let value: f64 = words
+            .next()
+            .and_then(|v| v.parse().ok())
+            .with_context(|| format!("no value for {key}"))?;
+        match key.to_lowercase().as_str() {
+            "ncols" => ncols = Some(value as usize),
+            "nrows" => nrows = Some(value as usize),
+            "xllcorner" => xll = Some(value),
+            "yllcorner" => yll = Some(value),
+            "xllcenter" => {
+                xll = Some(value);
+                corner = false;
+            }
+            "yllcenter" => {
+                yll = Some(value);
+                corner = false;
+            }
+            "cellsize" => {
+                dx = Some(value);
+                dy = Some(value);
+            }
+            "dx" => dx = Some(value),
+            "dy" => dy = Some(value),
+            "nodata_value" => nodata = Some(value),
+            _ => bail!("unknown header {key}"),
+        }
+    }
+    let (ncols, nrows) = (ncols.context("no ncols")?, nrows.context("no nrows")?);
+    if ncols == 0 || nrows == 0 {
+        bail!("the grid is empty, with {ncols} columns and {nrows} rows");
+    }
+    let (dx, dy) = (dx.context("no cellsize")?, dy.context("no cellsize")?);
+    let (mut x0, yll) = (xll.context("no xllcorner")?, yll.context("no yllcorner")?);
+    // The header describes the bottom-left, but rows go north to south
+    let mut y0 = yll + (nrows - 1) as f64 * dy;
+    if corner {
+        x0 += dx / 2.0;
+        y0 += dy / 2.0;
+    }
+
+    let values = words
+        .map(|w| {
+            let x: f64 = w.parse().with_context(|| format!("bad value {w}"))?;
+            Ok(if Some(x) == nodata {
+                f32::NAN
+            } else {
+                x as f32
+            })
+        })
+        .collect::<Result<Vec<_>>>()?;
+    Ok(Dem {
+        ncols,
+        nrows,
+        x0,
+        y0,
+        dx,
+        dy,
+        values,
+    })
+}
+
+fn read_geotiff(path: &str) -> Result<Dem> {
+    let mut decoder = Decoder::new(BufReader::new(std::fs::File::open(path)?))?;
+    let (ncols, nrows) = decoder.dimensions()?;
+    // [i, j, k, x, y, z], where raster point (i, j) is at (x, y)
+    let tiepoint = decoder.get_tag_f64_vec(Tag::ModelTiepointTag)?;
+    let scale = decoder.get_tag_f64_vec(Tag::ModelPixelScaleTag)?;
+    if tiepoint.len() < 6 || scale.len() < 2 {
+        bail!("missing georeferencing");
+    }
+    let nodata: Option<f64> = decoder
+        .get_tag_ascii_string(Tag::GdalNodata)
+        .ok()
+        .and_then(|x| x.trim_matches(char::from(0)).trim().parse().ok());
+    // 2 means signed integers, which the decoder hands back as unsigned
+    let signed = decoder.find_tag_unsigned::<u16>(Tag::SampleFormat)? == Some(2);
+
+    let values: Vec<f64> = match decoder.read_image()? {
+        DecodingResult::U8(x) => x.into_iter().map(|x| x as f64).collect(),
+        DecodingResult::U16(x) if signed => x.into_iter().map(|x| x as i16 as f64).collect(),
+        DecodingResult::U16(x) => x.into_iter().map(|x| x as f64).collect(),
+        DecodingResult::U32(x) if signed => x.into_iter().map(|x| x as i32 as f64).collect(),
+        DecodingResult::U32(x) => x.into_iter().map(|x| x as f64).collect(),
+        DecodingResult::U64(x) => x.into_iter().map(|x| x as f64).collect(),
+        DecodingResult::F32(x) => x.into_iter().map(|x| x as f64).collect(),
+        DecodingResult::F64(x) => x,
+    };
+
+    // Raster coordinates refer to the corner of a pixel
+    let (dx, dy) = (scale[0], scale[1]);
+    Ok(Dem {
+        ncols: ncols as usize,
+        nrows: nrows as usize,
+        x0: tiepoint[3] + (0.5 - tiepoint[0]) * dx,
+        y0: tiepoint[4] - (0.5 - tiepoint[1]) * dy,
+        dx,
+        dy,
+        values: values
+            .into_iter()
+            .map(|x| {
+                if Some(x) == nodata {
+                    f32::NAN
+                } else {
+                    x as f32
+                }
+            })
+            .collect(),
+    })
+}
+
+/// The DEM, cropped to the area of interest and transformed to meters. The lowest point becomes
+/// zero.
+#[derive(Resource)]
+pub struct Terrain {
+    /// Meters, increasing west to east
+    xs: Vec<f64>,
+    /// Meters, increasing south to north
+    ys: Vec<f64>,
+    /// Row-major, indexed by ys then xs. NaN where there's no data.
+    elevation: Vec<f32>,
+}
+
+impl Terrain {
+    /// Keeps the part of the DEM within `margin` meters of `bbox`
+    pub fn new(dem: &Dem, projection: Projection, bbox: Rect, margin: f64) -> Result<Terrain> {
+        // Longitude only affects X and latitude only affects Y, so the grid stays rectilinear
+        let col_x = |col: usize| {
+            projection
+                .to_meters(Coord {
+                    x: dem.x0 + col as f64 * dem.dx,
+                    y: dem.y0,
+                })
+                .x
+        };
+        let row_y = |row: usize| {
+            projection
+                .to_meters(Coord {
+                    x: dem.x0,
+                    y: dem.y0 - row as f64 * dem.dy,
+                })
+                .y
+        };
+        let cols: Vec<usize> = (0..dem.ncols)
+            .filter(|col| {
+                let x = col_x(*col);
+                x >= bbox.min().x - margin && x <= bbox.max().x + margin
+            })
+            .collect();
+        // Go south to north, so ys increase
+        let rows: Vec<usize> = (0..dem.nrows)
+            .rev()
+            .filter(|row| {
+                let y = row_y(*row);
+                y >= bbox.min().y - margin && y <= bbox.max().y + margin
+            })
+            .collect();
+        if cols.len() < 2 || rows.len() < 2 {
+            bail!("The DEM doesn't cover the buildings. Is it in WGS84?");
+        }
+
+        let step = cols.len().max(rows.len()).div_ceil(MAX_SAMPLES_PER_SIDE);
+        let cols: Vec<usize> = cols.into_iter().step_by(step).collect();
+        let rows: Vec<usize> = rows.into_iter().step_by(step).collect();
+
+        let mut elevation = Vec::with_capacity(cols.len() * rows.len());
+        for row in &rows {
+            for col in &cols {
+                elevation.push(dem.get(*col, *row));
+            }
+        }
+        let lowest = elevation
+            .iter()
+            .copied()
+            .filter(|x| !x.is_nan())
+            .fold(f32::MAX, f32::min);
+        if lowest == f32::MAX {
+            bail!("The DEM has no data around the buildings");
+        }
+        for x in &mut elevation {
+            *x -= lowest;
+        }
+
+        Ok(Terrain {
+            xs: cols.into_iter().map(col_x).collect(),
+            ys: rows.into_iter().map(row_y).collect(),
+            elevation,
+        })
+    }
+
+    /// In meters above the lowest point
+    pub fn highest(&self) -> f32 {
+        // max skips NaN
+        self.elevation.iter().copied().fold(0.0, f32::max)
+    }
+
+    fn get(&self, col: usize, row: usize) -> f32 {
+        self.elevation[row * self.xs.len() + col]
+    }
+
+    /// Interpolated between the nearest samples. None outside the DEM or where it has no data.
+    pub fn elevation(&self, c: Coord) -> Option<f32> {
+        let (col, tx) = locate(&self.xs, c.x)?;
+        let (row, ty) = locate(&self.ys, c.y)?;
+        let mut total = 0.0;
+        let mut weights = 0.0;
+        for (col, row, weight) in [
+            (col, row, (1.0 - tx) * (1.0 - ty)),
+            (col + 1, row, tx * (1.0 - ty)),
+            (col, row + 1, (1.0 - tx) * ty),
+            (col + 1, row + 1, tx * ty),
+        ] {
+            let value = self.get(col, row);
+            // Make do with whatever neighbors have data
+            if !value.is_nan() {
+                total += weight * value;
+                weights += weight;
+            }
+        }
+        (weights > 0.0).then(|| total / weights)
+    }
+
+    /// Where to put the floor of a building. Use the lowest corner, so nothing floats on a slope.
+    pub fn base_elevation(&self, polygon: &Polygon) -> f32 {
+        polygon
+            .exterior()
+            .coords()
+            .filter_map(|c| self.elevation(*c))
+            .reduce(f32::min)
+            .unwrap_or(0.0)
+    }
+
+    pub fn to_mesh(&self) -> Mesh {
+        let (ncols, nrows) = (self.xs.len(), self.ys.len());
+        let pos = |col: usize, row: usize| {
+            let elevation = self.get(col, row);
+            to_world(
+                Coord {
+                    x: self.xs[col],
+                    y: self.ys[row],
+                },
+                // These points aren't part of any triangle, but keep the bounding box sane
+                if elevation.is_nan() { 0.0 } else { elevation },
+            )
+        };
+
+        let mut builder = MeshBuilder::new();
+        for row in 0..nrows {
+            for col in 0..ncols {
+                // Slopes from the neighbors, or this point itself at the edge
+                let east = pos((col + 1).min(ncols - 1), row);
+                let west = pos(col.saturating_sub(1), row);
+                let north = pos(col, (row + 1).min(nrows - 1));
+                let south = pos(col, row.saturating_sub(1));
+                let normal = (east - west).cross(north - south).normalize_or_zero();
+                builder.add_vertex(Vertex {
+                    pos: pos(col, row),
+                    normal: if normal.is_nan() || normal == Vec3::ZERO {
+                        Vec3::Y
+                    } else {
+                        normal
+                    },
+                });
+            }
+        }
+
+        let idx = |col: usize, row: usize| (row * ncols + col) as u32;
+        for row in 0..nrows - 1 {
+            for col in 0..ncols - 1 {
+                let corners = [
+                    (col, row),
+                    (col + 1, row),
+                    (col + 1, row + 1),
+                    (col, row + 1),
+                ];
+                if corners.iter().any(|(c, r)| self.get(*c, *r).is_nan()) {
+                    continue;
+                }
+                // Counter-clockwise seen from above, starting southwest
+                let [sw, se, ne, nw] = corners.map(|(c, r)| idx(c, r));
+                builder.add_triangle(sw, se, ne);
+                builder.add_triangle(ne, nw, sw);
+            }
+        }
+        builder.build()
+    }
+}
+
+// Returns the index of the sample before x, and how far x is towards the next one
+fn locate(samples: &[f64], x: f64) -> Option<(usize, f32)> {
+    if x < samples[0] || x > *samples.last().unwrap() {
+        return None;
+    }
+    let idx = samples
+        .partition_point(|s| *s <= x)
+        .saturating_sub(1)
+        .min(samples.len() - 2);
+    let t = (x - samples[idx]) / (samples[idx + 1] - samples[idx]);
+    Some((idx, t as f32))
+}
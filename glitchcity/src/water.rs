@@ -0,0 +1,121 @@
+use bevy::prelude::{
+    Color, Component, Query, Res, ResMut, Resource, Time, Transform, Visibility, With,
+};
+use bevy_egui::egui;
+use geo::Area;
+
+use crate::animation::BuildingAnimation;
+use crate::buildings::{City, VerticalExaggeration, METERS_PER_LEVEL};
+use crate::colours::BuildingIdx;
+use crate::terrain::Terrain;
+
+/// What flooded buildings are tinted towards
+pub const WATER_COLOUR: Color = Color::rgb(0.1, 0.3, 0.6);
+
+/// Marks the water plane
+#[derive(Component)]
+pub struct Water;
+
+#[derive(Resource, Clone, PartialEq)]
+pub struct WaterSettings {
+    /// The height of the water, in meters, on the same scale as the terrain
+    pub level: f32,
+    /// Keep rising
+    pub animating: bool,
+    pub meters_per_second: f32,
+}
+
+impl Default for WaterSettings {
+    fn default() -> Self {
+        Self {
+            level: 0.0,
+            animating: false,
+            meters_per_second: 0.5,
+        }
+    }
+}
+
+#[derive(Resource, Default)]
+pub struct FloodStats {
+    buildings: usize,
+    /// Square meters of floors at least partly under water
+    floor_area: f64,
+}
+
+pub fn rise(time: Res<Time>, mut settings: ResMut<WaterSettings>) {
+    if settings.animating {
+        settings.level += settings.meters_per_second * time.delta_seconds();
+    }
+}
+
+pub fn update_water(
+    settings: Res<WaterSettings>,
+    mut query: Query<(&mut Transform, &mut Visibility), With<Water>>,
+) {
+    if !settings.is_changed() {
+        return;
+    }
+    for (mut transform, mut visibility) in &mut query {
+        transform.translation.y = settings.level;
+        visibility.is_visible = settings.level > 0.0;
+    }
+}
+
+/// Works out how much of each building is under water
+pub fn flood_buildings(
+    settings: Res<WaterSettings>,
+    exaggeration: Res<VerticalExaggeration>,
+    city: Res<City>,
+    mut stats: ResMut<FloodStats>,
+    mut query: Query<(&BuildingIdx, &mut BuildingAnimation)>,
+) {
+    if !settings.is_changed() && !exaggeration.is_changed() {
+        return;
+    }
+    *stats = FloodStats::default();
+    for (idx, mut animation) in &mut query {
+        let building = &city.buildings[idx.0];
+        let height = building.height * exaggeration.0;
+        let depth = (settings.level - building.base).clamp(0.0, height);
+        let submerged = if height > 0.0 { depth / height } else { 0.0 };
+        // Avoid triggering change detection, which would upload the material again
+        if animation.submerged != submerged {
+            animation.submerged = submerged;
+        }
+
+        if depth > 0.0 {
+            stats.buildings += 1;
+            let floors = (depth / exaggeration.0 / METERS_PER_LEVEL).ceil();
+            stats.floor_area += floors as f64 * building.polygon.unsigned_area();
+        }
+    }
+}
+
+/// High enough to cover the highest ground and then the tallest building
+pub fn max_level(city: &City, terrain: Option<&Terrain>, exaggeration: f32) -> f32 {
+    let tallest = city.buildings.iter().map(|b| b.height).fold(0.0, f32::max);
+    terrain.map_or(0.0, Terrain::highest) + tallest * exaggeration
+}
+
+pub fn controls(
+    ui: &mut egui::Ui,
+    settings: &mut WaterSettings,
+    stats: &FloodStats,
+    max_level: f32,
+) {
+    ui.separator();
+    ui.horizontal(|ui| {
+        ui.heading("Water");
+        ui.checkbox(&mut settings.animating, "rising");
+    });
+    ui.add(egui::Slider::new(&mut settings.level, 0.0..=max_level.max(1.0)).text("level (m)"));
+    ui.add(
+        egui::Slider::new(&mut settings.meters_per_second, 0.01..=5.0)
+            .logarithmic(true)
+            .text("meters per second"),
+    );
+    ui.label(format!(
+        "{} buildings affected, {:.0} m² of floors flooded",
+        stats.buildings, stats.floor_area
+    ));
+}
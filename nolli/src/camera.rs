@@ -0,0 +1,211 @@
+use bevy::prelude::{
+    default, Camera, Component, Input, KeyCode, Mut, OrthographicProjection, Query, Res, Transform,
+    UVec2, Vec2, Windows, With, Without,
+};
+use bevy::render::camera::Viewport;
+use bevy_pancam::PanCam;
+use geo::{Coord, Rect};
+
+use crate::load_geo::{DatasetIdx, Datasets};
+
+// Leave a little space around the data
+const MARGIN: f32 = 1.1;
+
+/// Shows another dataset, following the main camera's pan and zoom
+#[derive(Component)]
+pub struct LinkedCamera;
+
+/// Centers the view on a rectangle, zoomed out just enough to see it all in a viewport of the
+/// given logical size
+pub fn zoom_to_extent(
+    extent: Rect,
+    viewport_size: Vec2,
+    transform: &mut Transform,
+    projection: &mut OrthographicProjection,
+) {
+    let center = extent.center();
+    transform.translation.x = center.x as f32;
+    transform.translation.y = center.y as f32;
+    // By default, one world unit is one pixel before scaling
+    let scale_x = extent.width() as f32 / viewport_size.x;
+    let scale_y = extent.height() as f32 / viewport_size.y;
+    projection.scale = MARGIN * scale_x.max(scale_y);
+}
+
+/// Everything in every dataset. They share the view, so the largest decides the zoom.
+pub fn full_extent(datasets: &Datasets) -> Rect {
+    datasets
+        .0
+        .iter()
+        .map(|dataset| dataset.bbox)
+        .reduce(union)
+        .unwrap()
+}
+
+/// The smallest rectangle containing both
+pub fn union(a: Rect, b: Rect) -> Rect {
+    Rect::new(
+        Coord {
+            x: a.min().x.min(b.min().x),
+            y: a.min().y.min(b.min().y),
+        },
+        Coord {
+            x: a.max().x.max(b.max().x),
+            y: a.max().y.max(b.max().y),
+        },
+    )
+}
+
+/// How big each viewport is, with the window split between datasets
+pub fn viewport_size(datasets: &Datasets, window_size: Vec2) -> Vec2 {
+    Vec2::new(window_size.x / datasets.0.len() as f32, window_size.y)
+}
+
+/// Applies `zoom_to_extent` to the main camera. The others follow.
+pub fn zoom_main_camera(
+    datasets: &Datasets,
+    windows: &Windows,
+    cameras: &mut Query<(&mut Transform, &mut OrthographicProjection), With<PanCam>>,
+) {
+    let window = if let Some(window) = windows.get_primary() {
+        window
+    } else {
+        return;
+    };
+    let size = viewport_size(datasets, Vec2::new(window.width(), window.height()));
+    for (mut transform, mut projection) in cameras {
+        zoom_to_extent(full_extent(datasets), size, &mut transform, &mut projection);
+    }
+}
+
+pub fn zoom_to_extent_key(
+    keys: Res<Input<KeyCode>>,
+    datasets: Res<Datasets>,
+    windows: Res<Windows>,
+    mut cameras: Query<(&mut Transform, &mut OrthographicProjection), With<PanCam>>,
+) {
+    if keys.just_pressed(KeyCode::Home) {
+        zoom_main_camera(&datasets, &windows, &mut cameras);
+    }
+}
+
+/// Splits the window between datasets, and keeps every view at the same place and scale
+#[allow(clippy::type_complexity)]
+pub fn link_cameras(
+    windows: Res<Windows>,
+    datasets: Res<Datasets>,
+    mut main: Query<(&mut Camera, &Transform, &OrthographicProjection), With<PanCam>>,
+    mut linked: Query<
+        (
+            &DatasetIdx,
+            &mut Camera,
+            &mut Transform,
+            &mut OrthographicProjection,
+        ),
+        (With<LinkedCamera>, Without<PanCam>),
+    >,
+) {
+    if datasets.0.len() < 2 {
+        return;
+    }
+    let window = if let Some(window) = windows.get_primary() {
+        window
+    } else {
+        return;
+    };
+    let size = UVec2::new(
+        window.physical_width() / datasets.0.len() as u32,
+        window.physical_height(),
+    );
+    // Minimized
+    if size.x == 0 || size.y == 0 {
+        return;
+    }
+    let viewport = |idx: usize| {
+        Some(Viewport {
+            physical_position: UVec2::new(idx as u32 * size.x, 0),
+            physical_size: size,
+            ..default()
+        })
+    };
+
+    for (mut camera, transform, projection) in &mut main {
+        set_viewport(&mut camera, viewport(0));
+        for (idx, mut linked_camera, mut linked_transform, mut linked_projection) in &mut linked {
+            set_viewport(&mut linked_camera, viewport(idx.0));
+            if *linked_transform != *transform {
+                *linked_transform = *transform;
+            }
+            if linked_projection.scale != projection.scale {
+                linked_projection.scale = projection.scale;
+            }
+        }
+    }
+}
+
+/// Only touches the camera when the viewport really changes
+pub fn set_viewport(camera: &mut Mut<Camera>, viewport: Option<Viewport>) {
+    let unchanged = match (&camera.viewport, &viewport) {
+        (Some(a), Some(b)) => {
+            a.physical_position == b.physical_position && a.physical_size == b.physical_size
+        }
+        (None, None) => true,
+        _ => false,
+    };
+    if !unchanged {
+        camera.viewport = viewport;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use common::projection::Projection;
+
+    use super::*;
+    use crate::load_geo::Dataset;
+
+    fn dataset(bbox: Rect) -> Dataset {
+        Dataset {
+            path: String::new(),
+            buildings: Vec::new(),
+            bbox,
+            projection: Projection::new(Coord::zero()),
+            other_features: Vec::new(),
+            edited: false,
+        }
+    }
+
+    #[test]
+    fn full_extent_covers_every_dataset() {
+        let datasets = Datasets(vec![
+            dataset(Rect::new((0.0, 0.0), (100.0, 50.0))),
+            dataset(Rect::new((-20.0, 10.0), (80.0, 200.0))),
+        ]);
+        assert_eq!(
+            full_extent(&datasets),
+            Rect::new((-20.0, 0.0), (100.0, 200.0))
+        );
+    }
+
+    #[test]
+    fn side_by_side_viewports_fit_the_taller_extent() {
+        let datasets = Datasets(vec![
+            dataset(Rect::new((0.0, 0.0), (100.0, 100.0))),
+            dataset(Rect::new((0.0, 0.0), (100.0, 100.0))),
+        ]);
+        let size = viewport_size(&datasets, Vec2::new(800.0, 200.0));
+        assert_eq!(size, Vec2::new(400.0, 200.0));
+
+        let mut transform = Transform::IDENTITY;
+        let mut projection = OrthographicProjection::default();
+        zoom_to_extent(
+            full_extent(&datasets),
+            size,
+            &mut transform,
+            &mut projection,
+        );
+        assert_eq!(transform.translation.truncate(), Vec2::new(50.0, 50.0));
+        // Half a meter per pixel fits the height, plus the margin
+        assert!((projection.scale - MARGIN * 0.5).abs() < 1e-6);
+    }
+}
@@ -0,0 +1,62 @@
+use bevy::core_pipeline::clear_color::ClearColorConfig;
+use bevy::prelude::{
+    default, BuildChildren, Camera, Camera2d, Camera2dBundle, Commands, Entity,
+    OrthographicProjection, Query, Res, Transform, With, Without,
+};
+use common::capture::{Capture, CaptureCamera, CaptureInto};
+
+use crate::load_geo::DatasetIdx;
+
+/// Once recording starts, renders every dataset's view into one image too, split the same way as
+/// the window
+#[allow(clippy::type_complexity)]
+pub fn spawn_cameras(
+    mut commands: Commands,
+    capture: Res<Capture>,
+    views: Query<(Entity, &Camera, &OrthographicProjection, &DatasetIdx), Without<CaptureCamera>>,
+    capture_cameras: Query<(), With<CaptureCamera>>,
+) {
+    let export = match capture.export() {
+        Some(export) if capture_cameras.is_empty() => export,
+        _ => return,
+    };
+    let mut views: Vec<_> = views.iter().collect();
+    views.sort_by_key(|(_, _, _, idx)| idx.0);
+
+    // The first view saves the image and clears it. The others draw on top.
+    let mut saving = None;
+    for (entity, camera, projection, idx) in &views {
+        let bundle = Camera2dBundle {
+            camera: Camera {
+                // Draw before the views in the window, which clear it anyway
+                priority: idx.0 as isize - views.len() as isize,
+                viewport: camera.viewport.clone(),
+                ..default()
+            },
+            camera_2d: Camera2d {
+                clear_color: if saving.is_none() {
+                    ClearColorConfig::Default
+                } else {
+                    ClearColorConfig::None
+                },
+            },
+            projection: (*projection).clone(),
+            // Sit exactly where the parent is, not further back again
+            transform: Transform::IDENTITY,
+            ..default()
+        };
+        let child = match saving {
+            None => {
+                let child = commands
+                    .spawn((bundle, export.clone(), CaptureCamera, idx.layer()))
+                    .id();
+                saving = Some(child);
+                child
+            }
+            Some(into) => commands
+                .spawn((bundle, CaptureInto(into), CaptureCamera, idx.layer()))
+                .id(),
+        };
+        commands.entity(*entity).add_child(child);
+    }
+}
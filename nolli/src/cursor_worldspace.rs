@@ -1,32 +1,171 @@
-use bevy::prelude::{Camera, GlobalTransform, Query, Res, ResMut, Resource, Vec2};
+use bevy::prelude::{
+    Camera, Entity, GlobalTransform, Input, MouseButton, Query, Res, ResMut, Resource, Vec2, With,
+};
 use bevy::render::camera::RenderTarget;
 use bevy::window::Windows;
+use bevy_egui::EguiContext;
 
+use crate::load_geo::DatasetIdx;
+
+/// Where the cursor is in the world, and through which camera. With overlapping cameras, the one
+/// drawn on top wins.
 #[derive(Resource, Default, Debug)]
-pub struct CursorWorldspace(pub Option<Vec2>);
+pub struct CursorWorldspace {
+    pub pos: Option<Vec2>,
+    pub camera: Option<Entity>,
+}
+
+impl CursorWorldspace {
+    /// The dataset shown through the camera under the cursor
+    pub fn dataset(&self, cameras: &Query<&DatasetIdx, With<Camera>>) -> Option<DatasetIdx> {
+        cameras.get(self.camera?).ok().copied()
+    }
+}
 
-// From https://bevy-cheatbook.github.io/cookbook/cursor2world.html
+// Based on https://bevy-cheatbook.github.io/cookbook/cursor2world.html
 pub fn cursor_to_world(
     windows: Res<Windows>,
-    query: Query<(&Camera, &GlobalTransform)>,
+    query: Query<(Entity, &Camera, &GlobalTransform)>,
     mut cursor: ResMut<CursorWorldspace>,
 ) {
-    // Assume exactly one camera
-    let (camera, camera_transform) = query.single();
-    let window = if let RenderTarget::Window(id) = camera.target {
-        windows.get(id).unwrap()
-    } else {
-        windows.get_primary().unwrap()
-    };
-
-    if let Some(screen_pos) = window.cursor_position() {
-        let window_size = Vec2::new(window.width() as f32, window.height() as f32);
-        let ndc = (screen_pos / window_size) * 2.0 - Vec2::ONE;
+    let mut candidates = Vec::new();
+    for (entity, camera, camera_transform) in &query {
+        if !camera.is_active {
+            continue;
+        }
+        // Cameras rendering to images, like the one capturing frames, aren't under the cursor
+        let window = if let RenderTarget::Window(id) = camera.target {
+            if let Some(window) = windows.get(id) {
+                window
+            } else {
+                continue;
+            }
+        } else {
+            continue;
+        };
+        if let (Some(screen_pos), Some(viewport)) =
+            (window.cursor_position(), camera.logical_viewport_rect())
+        {
+            // The cursor's origin is the bottom-left, but the viewport's is the top-left
+            let from_top = Vec2::new(screen_pos.x, window.height() - screen_pos.y);
+            candidates.push((entity, camera, camera_transform, from_top, viewport));
+        }
+    }
+
+    let best = topmost(
+        candidates
+            .iter()
+            .map(|(_, camera, _, from_top, viewport)| (camera.priority, *from_top, *viewport)),
+    )
+    .map(|(idx, ndc)| {
+        let (entity, camera, camera_transform, _, _) = candidates[idx];
         let ndc_to_world = camera_transform.compute_matrix() * camera.projection_matrix().inverse();
         let world_pos = ndc_to_world.project_point3(ndc.extend(-1.0));
-        let world_pos: Vec2 = world_pos.truncate();
-        cursor.0 = Some(world_pos);
-    } else {
-        cursor.0 = None;
+        (world_pos.truncate(), entity)
+    });
+
+    cursor.pos = best.map(|(pos, _)| pos);
+    cursor.camera = best.map(|(_, entity)| entity);
+}
+
+/// Of the viewports under the cursor, the one drawn on top, with the highest priority. Each comes
+/// with the cursor, from the top-left of its window. Returns the index of the winner and where the
+/// cursor is inside it, from -1 to 1 with Y up.
+fn topmost(viewports: impl Iterator<Item = (isize, Vec2, (Vec2, Vec2))>) -> Option<(usize, Vec2)> {
+    let mut best: Option<(isize, usize, Vec2)> = None;
+    for (idx, (priority, from_top, viewport)) in viewports.enumerate() {
+        if best.is_some_and(|(best_priority, _, _)| best_priority >= priority) {
+            continue;
+        }
+        if let Some(ndc) = to_ndc(from_top, viewport) {
+            best = Some((priority, idx, ndc));
+        }
+    }
+    best.map(|(_, idx, ndc)| (idx, ndc))
+}
+
+/// Where the cursor is inside a viewport, from -1 to 1 with Y up. None outside it.
+fn to_ndc(from_top: Vec2, (min, max): (Vec2, Vec2)) -> Option<Vec2> {
+    if from_top.cmplt(min).any() || from_top.cmpge(max).any() {
+        return None;
+    }
+    let within_viewport = Vec2::new(from_top.x - min.x, max.y - from_top.y);
+    Some((within_viewport / (max - min)) * 2.0 - Vec2::ONE)
+}
+
+// The cursor can wander this many pixels during a click. Further, and it's a pan.
+const CLICK_TOLERANCE_PIXELS: f32 = 5.0;
+
+/// Tells clicks on the map apart from dragging to pan and clicking on egui. Keep one in a `Local`.
+#[derive(Default)]
+pub struct ClickDetector {
+    pressed_at: Option<Vec2>,
+}
+
+impl ClickDetector {
+    /// True when the left button was just released, close to where it was pressed on the map
+    pub fn clicked(
+        &mut self,
+        ctx: &mut EguiContext,
+        mouse: &Input<MouseButton>,
+        windows: &Windows,
+    ) -> bool {
+        let screen = windows.get_primary().and_then(|w| w.cursor_position());
+        if mouse.just_pressed(MouseButton::Left) {
+            self.pressed_at = if ctx.ctx_mut().wants_pointer_input() {
+                None
+            } else {
+                screen
+            };
+        }
+        if !mouse.just_released(MouseButton::Left) {
+            return false;
+        }
+        matches!(
+            (self.pressed_at.take(), screen),
+            (Some(start), Some(end)) if start.distance(end) <= CLICK_TOLERANCE_PIXELS
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Two 400 by 300 viewports side by side, like comparing two datasets
+    const LEFT: (Vec2, Vec2) = (Vec2::new(0.0, 0.0), Vec2::new(400.0, 300.0));
+    const RIGHT: (Vec2, Vec2) = (Vec2::new(400.0, 0.0), Vec2::new(800.0, 300.0));
+
+    fn pick(viewports: &[(isize, (Vec2, Vec2))], x: f32, y: f32) -> Option<(usize, Vec2)> {
+        let from_top = Vec2::new(x, y);
+        topmost(
+            viewports
+                .iter()
+                .map(|(priority, viewport)| (*priority, from_top, *viewport)),
+        )
+    }
+
+    #[test]
+    fn split_screen_picks_the_viewport_under_the_cursor() {
+        let viewports = [(0, LEFT), (-1, RIGHT)];
+        let (idx, ndc) = pick(&viewports, 500.0, 100.0).unwrap();
+        assert_eq!(idx, 1);
+        assert!(ndc.abs_diff_eq(Vec2::new(-0.5, 1.0 / 3.0), 1e-6), "{ndc}");
+
+        let (idx, ndc) = pick(&viewports, 200.0, 150.0).unwrap();
+        assert_eq!(idx, 0);
+        assert_eq!(ndc, Vec2::ZERO);
+    }
+
+    #[test]
+    fn overlapping_cameras_pick_the_one_on_top() {
+        // Like the minimap drawn over the main view
+        let minimap = (Vec2::new(0.0, 0.0), Vec2::new(100.0, 100.0));
+        let full = (Vec2::new(0.0, 0.0), Vec2::new(800.0, 300.0));
+        let viewports = [(0, full), (1, minimap)];
+        let idx = |x, y| pick(&viewports, x, y).map(|(idx, _)| idx);
+        assert_eq!(idx(50.0, 50.0), Some(1));
+        assert_eq!(idx(50.0, 250.0), Some(0));
+        assert_eq!(idx(900.0, 50.0), None);
     }
 }
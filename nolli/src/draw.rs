@@ -0,0 +1,384 @@
+use bevy::prelude::{
+    Assets, Camera, Color, Commands, Component, DetectChanges, Entity, EventReader, EventWriter,
+    Input, KeyCode, Local, Mesh, MouseButton, Or, Query, Res, ResMut, Resource, Transform, Vec2,
+    Vec3, Windows, With,
+};
+use bevy::sprite::Mesh2dHandle;
+use bevy_egui::{egui, EguiContext};
+use bevy_prototype_lyon::prelude::{DrawMode, GeometryBuilder, StrokeMode};
+use bevy_prototype_lyon::shapes;
+use geo::{BoundingRect, Contains, Coord, LineString, Point, Polygon, Rect, Translate};
+use geojson::JsonObject;
+
+use crate::camera;
+use crate::cursor_worldspace::{ClickDetector, CursorWorldspace};
+use crate::grid::Grid;
+use crate::load_geo::{self, Building, DatasetIdx, Datasets};
+use crate::selection::Selection;
+use crate::{BuildingsMesh, LoadedGrid, OriginalGrid, Tool};
+
+/// Sent after a building is added, removed, or moved. The mesh and grids are redone over the
+/// region, in meters.
+pub struct BuildingsChanged {
+    pub dataset: DatasetIdx,
+    pub region: Rect,
+}
+
+#[derive(Resource)]
+pub struct Drawing {
+    /// Where the points are
+    dataset: DatasetIdx,
+    /// Corners of a new building so far, in meters
+    points: Vec<Vec2>,
+    moving: Option<Moving>,
+    /// Exporting applies to this one
+    export_dataset: DatasetIdx,
+    path: String,
+    /// The result of the last export
+    status: String,
+}
+
+impl Default for Drawing {
+    fn default() -> Self {
+        Self {
+            dataset: DatasetIdx(0),
+            points: Vec::new(),
+            moving: None,
+            export_dataset: DatasetIdx(0),
+            path: "edited.geojson".to_string(),
+            status: String::new(),
+        }
+    }
+}
+
+/// A building being dragged with the move tool
+struct Moving {
+    dataset: DatasetIdx,
+    building: usize,
+    /// Where the drag started and is now
+    from: Vec2,
+    to: Vec2,
+}
+
+impl Moving {
+    fn polygon(&self, datasets: &Datasets) -> Polygon {
+        let offset = self.to - self.from;
+        datasets.0[self.dataset.0].buildings[self.building]
+            .polygon
+            .translate(offset.x as f64, offset.y as f64)
+    }
+}
+
+/// Marks the outlines drawn for a building being drawn or moved
+#[derive(Component)]
+pub struct DrawingShape;
+
+/// With the draw tool, clicking adds a corner. Enter finishes the building, backspace removes the
+/// last corner, and escape starts over.
+#[allow(clippy::too_many_arguments)]
+pub fn draw_on_click(
+    mut ctx: ResMut<EguiContext>,
+    mut click: Local<ClickDetector>,
+    tool: Res<Tool>,
+    mut drawing: ResMut<Drawing>,
+    mut datasets: ResMut<Datasets>,
+    mut events: EventWriter<BuildingsChanged>,
+    mouse: Res<Input<MouseButton>>,
+    keys: Res<Input<KeyCode>>,
+    windows: Res<Windows>,
+    cursor: Res<CursorWorldspace>,
+    cameras: Query<&DatasetIdx, With<Camera>>,
+) {
+    let clicked = click.clicked(&mut ctx, &mouse, &windows);
+    if *tool != Tool::Draw {
+        return;
+    }
+    if clicked {
+        if let (Some(pt), Some(dataset)) = (cursor.pos, cursor.dataset(&cameras)) {
+            if drawing.dataset != dataset {
+                drawing.points.clear();
+                drawing.dataset = dataset;
+            }
+            drawing.points.push(pt);
+        }
+    }
+    if keys.just_pressed(KeyCode::Return) {
+        finish(&mut drawing, &mut datasets, &mut events);
+    }
+    if keys.just_pressed(KeyCode::Back) {
+        drawing.points.pop();
+    }
+    if keys.just_pressed(KeyCode::Escape) {
+        drawing.points.clear();
+    }
+}
+
+// Adds the drawn building, if it has enough corners
+fn finish(
+    drawing: &mut Drawing,
+    datasets: &mut Datasets,
+    events: &mut EventWriter<BuildingsChanged>,
+) {
+    if drawing.points.len() < 3 {
+        return;
+    }
+    let ring: Vec<Coord> = drawing
+        .points
+        .drain(..)
+        .map(|pt| Coord {
+            x: pt.x as f64,
+            y: pt.y as f64,
+        })
+        .collect();
+    let polygon = Polygon::new(LineString::from(ring), Vec::new());
+    let region = polygon.bounding_rect().unwrap();
+    datasets.0[drawing.dataset.0].buildings.push(Building {
+        polygon,
+        properties: JsonObject::new(),
+        feature: None,
+        id: None,
+    });
+    events.send(BuildingsChanged {
+        dataset: drawing.dataset,
+        region,
+    });
+}
+
+/// With the move tool, dragging a building moves it
+#[allow(clippy::too_many_arguments)]
+pub fn move_on_drag(
+    mut ctx: ResMut<EguiContext>,
+    tool: Res<Tool>,
+    mut drawing: ResMut<Drawing>,
+    mut datasets: ResMut<Datasets>,
+    mut events: EventWriter<BuildingsChanged>,
+    mouse: Res<Input<MouseButton>>,
+    cursor: Res<CursorWorldspace>,
+    cameras: Query<&DatasetIdx, With<Camera>>,
+) {
+    if *tool != Tool::Move {
+        return;
+    }
+    if mouse.just_pressed(MouseButton::Left) && !ctx.ctx_mut().wants_pointer_input() {
+        if let (Some(pt), Some(dataset)) = (cursor.pos, cursor.dataset(&cameras)) {
+            let point = Point::new(pt.x as f64, pt.y as f64);
+            if let Some(building) = datasets.0[dataset.0]
+                .buildings
+                .iter()
+                .position(|b| b.polygon.contains(&point))
+            {
+                drawing.moving = Some(Moving {
+                    dataset,
+                    building,
+                    from: pt,
+                    to: pt,
+                });
+            }
+        }
+    }
+
+    // Only touch the drawing when the cursor really moves, so the outline isn't redrawn every frame
+    let to = match (&drawing.moving, cursor.pos) {
+        (Some(moving), Some(pt)) if moving.to != pt => Some(pt),
+        _ => None,
+    };
+    if let Some(pt) = to {
+        if let Some(ref mut moving) = drawing.moving {
+            moving.to = pt;
+        }
+    }
+
+    if !mouse.just_released(MouseButton::Left) {
+        return;
+    }
+    if let Some(moving) = drawing.moving.take() {
+        if moving.to == moving.from {
+            return;
+        }
+        let polygon = moving.polygon(&datasets);
+        let building = &mut datasets.0[moving.dataset.0].buildings[moving.building];
+        // Redo the grid where the building was and where it is now
+        let region = camera::union(
+            building.polygon.bounding_rect().unwrap(),
+            polygon.bounding_rect().unwrap(),
+        );
+        building.polygon = polygon;
+        events.send(BuildingsChanged {
+            dataset: moving.dataset,
+            region,
+        });
+    }
+}
+
+/// With the select tool, the delete key removes the selected building
+pub fn delete_key(
+    keys: Res<Input<KeyCode>>,
+    tool: Res<Tool>,
+    mut selection: ResMut<Selection>,
+    mut datasets: ResMut<Datasets>,
+    mut events: EventWriter<BuildingsChanged>,
+) {
+    if *tool == Tool::Select && keys.just_pressed(KeyCode::Delete) {
+        delete_selected(&mut selection, &mut datasets, &mut events);
+    }
+}
+
+pub fn delete_selected(
+    selection: &mut Selection,
+    datasets: &mut Datasets,
+    events: &mut EventWriter<BuildingsChanged>,
+) {
+    if let Some((dataset, idx)) = selection.0.take() {
+        let building = datasets.0[dataset.0].buildings.remove(idx);
+        events.send(BuildingsChanged {
+            dataset,
+            region: building.polygon.bounding_rect().unwrap(),
+        });
+    }
+}
+
+/// Rebuilds the buildings' mesh and redoes the grids where buildings changed. Painted and erased
+/// cells there are redone too.
+#[allow(clippy::type_complexity)]
+pub fn update_buildings(
+    mut events: EventReader<BuildingsChanged>,
+    mut datasets: ResMut<Datasets>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mesh_handles: Query<(&Mesh2dHandle, &DatasetIdx), With<BuildingsMesh>>,
+    mut grids: Query<(&mut Grid, &DatasetIdx), Or<(With<OriginalGrid>, With<LoadedGrid>)>>,
+) {
+    let mut remeshed = Vec::new();
+    for event in events.iter() {
+        datasets.0[event.dataset.0].edited = true;
+        let polygons: Vec<Polygon> = datasets.0[event.dataset.0]
+            .buildings
+            .iter()
+            .map(|b| b.polygon.clone())
+            .collect();
+        for (mut grid, idx) in &mut grids {
+            if *idx == event.dataset {
+                grid.rasterize(&polygons, event.region);
+            }
+        }
+        if remeshed.contains(&event.dataset) {
+            continue;
+        }
+        remeshed.push(event.dataset);
+        for (handle, idx) in &mesh_handles {
+            if *idx == event.dataset {
+                if let Some(mesh) = meshes.get_mut(&handle.0) {
+                    *mesh = load_geo::polygons_to_mesh(polygons.clone());
+                }
+            }
+        }
+    }
+}
+
+pub fn draw_outlines(
+    drawing: Res<Drawing>,
+    datasets: Res<Datasets>,
+    mut commands: Commands,
+    query: Query<Entity, With<DrawingShape>>,
+) {
+    if !drawing.is_changed() {
+        return;
+    }
+    for entity in &query {
+        commands.entity(entity).despawn();
+    }
+
+    let (points, closed, dataset) = if let Some(ref moving) = drawing.moving {
+        let polygon = moving.polygon(&datasets);
+        let points = polygon
+            .exterior()
+            .coords()
+            .map(|c| Vec2::new(c.x as f32, c.y as f32))
+            .collect();
+        (points, true, moving.dataset)
+    } else {
+        (drawing.points.clone(), false, drawing.dataset)
+    };
+    if points.is_empty() {
+        return;
+    }
+
+    let mut builder = GeometryBuilder::new();
+    if !closed {
+        for pt in &points {
+            builder = builder.add(&shapes::Circle {
+                radius: 2.0,
+                center: *pt,
+            });
+        }
+    }
+    builder = builder.add(&shapes::Polygon { points, closed });
+    commands.spawn((
+        builder.build(
+            DrawMode::Stroke(StrokeMode::new(Color::hex("FFC20A").unwrap(), 1.5)),
+            // Above the flood and selection
+            Transform::from_translation(Vec3::new(0.0, 0.0, 3.0)),
+        ),
+        DrawingShape,
+        dataset.layer(),
+    ));
+}
+
+pub fn controls(
+    mut ctx: ResMut<EguiContext>,
+    tool: Res<Tool>,
+    mut drawing: ResMut<Drawing>,
+    mut datasets: ResMut<Datasets>,
+    mut events: EventWriter<BuildingsChanged>,
+) {
+    if !matches!(*tool, Tool::Draw | Tool::Move) {
+        return;
+    }
+    // Only mark the drawing changed on a real edit, or draw_outlines redraws every frame
+    let mut changed = false;
+    let state = drawing.bypass_change_detection();
+    egui::Window::new("Buildings").show(ctx.ctx_mut(), |ui| {
+        if *tool == Tool::Draw {
+            ui.label("Click to add corners, enter to finish, backspace to undo, escape to cancel");
+            ui.horizontal(|ui| {
+                if ui
+                    .add_enabled(state.points.len() >= 3, egui::Button::new("Finish"))
+                    .clicked()
+                {
+                    finish(state, &mut datasets, &mut events);
+                    changed = true;
+                }
+                if ui.button("Cancel").clicked() {
+                    state.points.clear();
+                    changed = true;
+                }
+            });
+        } else {
+            ui.label("Drag a building to move it. Right drag to pan.");
+        }
+
+        ui.separator();
+        if datasets.0.len() > 1 {
+            for (idx, dataset) in datasets.0.iter().enumerate() {
+                changed |= ui
+                    .radio_value(&mut state.export_dataset, DatasetIdx(idx), &dataset.path)
+                    .changed();
+            }
+        }
+        ui.horizontal(|ui| {
+            changed |= ui.text_edit_singleline(&mut state.path).changed();
+            if ui.button("Export GeoJSON").clicked() {
+                state.status = match datasets.0[state.export_dataset.0].save_geojson(&state.path) {
+                    Ok(()) => format!("Saved {}", state.path),
+                    Err(err) => err.to_string(),
+                };
+                changed = true;
+            }
+        });
+        if !state.status.is_empty() {
+            ui.label(&state.status);
+        }
+    });
+    if changed {
+        drawing.set_changed();
+    }
+}
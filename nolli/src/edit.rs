@@ -0,0 +1,328 @@
+use anyhow::Result;
+use bevy::prelude::{
+    Camera, ChangeTrackers, Changed, ClearColor, Color, Commands, Component, Entity, Input,
+    KeyCode, Local, MouseButton, ParamSet, Query, Res, ResMut, Resource, With,
+};
+use bevy_egui::{egui, EguiContext};
+use bevy_pancam::PanCam;
+
+use crate::cursor_worldspace::CursorWorldspace;
+use crate::grid::Grid;
+use crate::load_geo::{DatasetIdx, Datasets};
+use crate::{ActiveGrid, LoadedGrid, OriginalGrid, Tool};
+
+/// Cells flipped by one brush stroke or load, to undo or redo
+struct Stroke {
+    dataset: DatasetIdx,
+    /// Each cell, and whether it was a building before
+    cells: Vec<(usize, usize, bool)>,
+}
+
+#[derive(Resource)]
+pub struct Editing {
+    /// In meters
+    pub radius: f64,
+    undo: Vec<Stroke>,
+    redo: Vec<Stroke>,
+    /// The stroke happening now
+    stroke: Option<Stroke>,
+    /// Saving and loading apply to this one
+    dataset: DatasetIdx,
+    path: String,
+    /// The result of the last save or load
+    status: String,
+}
+
+impl Default for Editing {
+    fn default() -> Self {
+        Self {
+            radius: 15.0,
+            undo: Vec::new(),
+            redo: Vec::new(),
+            stroke: None,
+            dataset: DatasetIdx(0),
+            path: "scenario.asc".to_string(),
+            status: String::new(),
+        }
+    }
+}
+
+/// Marks the cells drawn where buildings were painted or erased
+#[derive(Component)]
+pub struct EditedCells;
+
+/// With the paint or erase tool, dragging changes the buildings under the brush
+#[allow(clippy::too_many_arguments)]
+pub fn paint(
+    mut ctx: ResMut<EguiContext>,
+    tool: Res<Tool>,
+    mouse: Res<Input<MouseButton>>,
+    cursor: Res<CursorWorldspace>,
+    cameras: Query<&DatasetIdx, With<Camera>>,
+    mut editing: ResMut<Editing>,
+    mut originals: Query<(&mut Grid, &DatasetIdx), With<OriginalGrid>>,
+) {
+    let building = match *tool {
+        Tool::Paint => true,
+        Tool::Erase => false,
+        _ => return,
+    };
+    let dataset = cursor.dataset(&cameras);
+    if mouse.just_pressed(MouseButton::Left) && !ctx.ctx_mut().wants_pointer_input() {
+        if let Some(dataset) = dataset {
+            editing.stroke = Some(Stroke {
+                dataset,
+                cells: Vec::new(),
+            });
+            editing.dataset = dataset;
+        }
+    }
+    if mouse.just_released(MouseButton::Left) {
+        if let Some(stroke) = editing.stroke.take() {
+            if !stroke.cells.is_empty() {
+                editing.undo.push(stroke);
+                editing.redo.clear();
+            }
+        }
+        return;
+    }
+
+    let editing = &mut *editing;
+    let (stroke, pt) = match (&mut editing.stroke, cursor.pos) {
+        (Some(stroke), Some(pt)) if dataset == Some(stroke.dataset) => (stroke, pt),
+        _ => return,
+    };
+    for (mut grid, idx) in &mut originals {
+        if *idx != stroke.dataset {
+            continue;
+        }
+        for (x, y) in grid.cells_near(pt, editing.radius) {
+            if grid.is_building(x, y) != building {
+                stroke.cells.push((x, y, !building));
+                grid.set_building(x, y, building);
+            }
+        }
+    }
+}
+
+/// Ctrl+Z undoes the last stroke, and Ctrl+Y or Ctrl+Shift+Z redoes it
+pub fn undo_keys(
+    keys: Res<Input<KeyCode>>,
+    mut editing: ResMut<Editing>,
+    mut originals: Query<(&mut Grid, &DatasetIdx), With<OriginalGrid>>,
+) {
+    if !keys.any_pressed([KeyCode::LControl, KeyCode::RControl]) {
+        return;
+    }
+    let shift = keys.any_pressed([KeyCode::LShift, KeyCode::RShift]);
+    if keys.just_pressed(KeyCode::Y) || (shift && keys.just_pressed(KeyCode::Z)) {
+        redo(&mut editing, &mut originals);
+    } else if keys.just_pressed(KeyCode::Z) {
+        undo(&mut editing, &mut originals);
+    }
+}
+
+fn undo(
+    editing: &mut Editing,
+    originals: &mut Query<(&mut Grid, &DatasetIdx), With<OriginalGrid>>,
+) {
+    if let Some(stroke) = editing.undo.pop() {
+        flip(&stroke, true, originals);
+        editing.redo.push(stroke);
+    }
+}
+
+fn redo(
+    editing: &mut Editing,
+    originals: &mut Query<(&mut Grid, &DatasetIdx), With<OriginalGrid>>,
+) {
+    if let Some(stroke) = editing.redo.pop() {
+        flip(&stroke, false, originals);
+        editing.undo.push(stroke);
+    }
+}
+
+// Sets every cell in the stroke to how it was before, or after
+fn flip(
+    stroke: &Stroke,
+    before: bool,
+    originals: &mut Query<(&mut Grid, &DatasetIdx), With<OriginalGrid>>,
+) {
+    for (mut grid, idx) in originals {
+        if *idx != stroke.dataset {
+            continue;
+        }
+        for (x, y, was_building) in &stroke.cells {
+            grid.set_building(*x, *y, *was_building == before);
+        }
+    }
+}
+
+/// After the buildings change, flood again from the same places. Replaying a whole flood is slow,
+/// so during a brush stroke, wait until the mouse is released.
+#[allow(clippy::type_complexity)]
+pub fn reflood(
+    editing: Res<Editing>,
+    mut pending: Local<Vec<DatasetIdx>>,
+    mut set: ParamSet<(
+        Query<(&Grid, &DatasetIdx, ChangeTrackers<Grid>), With<OriginalGrid>>,
+        Query<(&mut Grid, &DatasetIdx), With<ActiveGrid>>,
+    )>,
+) {
+    let mut changed: Vec<(Grid, DatasetIdx)> = Vec::new();
+    for (grid, idx, trackers) in &set.p0() {
+        if trackers.is_changed() && !pending.contains(idx) {
+            pending.push(*idx);
+        }
+        if editing.stroke.is_none() && pending.contains(idx) {
+            changed.push((grid.clone(), *idx));
+        }
+    }
+    if changed.is_empty() {
+        return;
+    }
+    pending.clear();
+    for (mut active, idx) in &mut set.p1() {
+        if let Some((original, _)) = changed.iter().find(|(_, i)| i == idx) {
+            let mut grid = original.clone();
+            grid.replay_flood(active.seeds(), active.step());
+            *active = grid;
+        }
+    }
+}
+
+#[allow(clippy::type_complexity)]
+pub fn render_edits(
+    mut commands: Commands,
+    clear_color: Res<ClearColor>,
+    originals: Query<(&Grid, &DatasetIdx), (With<OriginalGrid>, Changed<Grid>)>,
+    loaded: Query<(&Grid, &DatasetIdx), With<LoadedGrid>>,
+    drawn: Query<(Entity, &DatasetIdx), With<EditedCells>>,
+) {
+    for (grid, idx) in &originals {
+        for (entity, drawn_idx) in &drawn {
+            if drawn_idx == idx {
+                commands.entity(entity).despawn();
+            }
+        }
+        if let Some((loaded, _)) = loaded.iter().find(|(_, i)| *i == idx) {
+            // Painted cells look like the buildings, and erased ones like empty space
+            let painted = Color::hex("601865").unwrap();
+            for bundle in grid.render_edits(loaded, painted, clear_color.0) {
+                commands.spawn((bundle, EditedCells, *idx, idx.layer()));
+            }
+        }
+    }
+}
+
+/// While painting or moving buildings, only the right and middle buttons pan
+pub fn pan_buttons(tool: Res<Tool>, mut cameras: Query<&mut PanCam>) {
+    if !tool.is_changed() {
+        return;
+    }
+    for mut pancam in &mut cameras {
+        pancam.grab_buttons = if tool.drags() {
+            vec![MouseButton::Right, MouseButton::Middle]
+        } else {
+            vec![MouseButton::Left, MouseButton::Right, MouseButton::Middle]
+        };
+    }
+}
+
+pub fn controls(
+    mut ctx: ResMut<EguiContext>,
+    tool: Res<Tool>,
+    datasets: Res<Datasets>,
+    mut editing: ResMut<Editing>,
+    mut originals: Query<(&mut Grid, &DatasetIdx), With<OriginalGrid>>,
+) {
+    if !matches!(*tool, Tool::Paint | Tool::Erase) {
+        return;
+    }
+    let editing = &mut *editing;
+    egui::Window::new("Edit").show(ctx.ctx_mut(), |ui| {
+        ui.label("Drag to paint or erase buildings. Right drag to pan.");
+        ui.add(egui::Slider::new(&mut editing.radius, 5.0..=100.0).text("brush radius (m)"));
+        ui.horizontal(|ui| {
+            if ui
+                .add_enabled(!editing.undo.is_empty(), egui::Button::new("Undo (Ctrl+Z)"))
+                .clicked()
+            {
+                undo(editing, &mut originals);
+            }
+            if ui
+                .add_enabled(!editing.redo.is_empty(), egui::Button::new("Redo (Ctrl+Y)"))
+                .clicked()
+            {
+                redo(editing, &mut originals);
+            }
+        });
+
+        ui.separator();
+        if datasets.0.len() > 1 {
+            for (idx, dataset) in datasets.0.iter().enumerate() {
+                ui.radio_value(&mut editing.dataset, DatasetIdx(idx), &dataset.path);
+            }
+        }
+        ui.horizontal(|ui| {
+            ui.text_edit_singleline(&mut editing.path);
+            if ui.button("Save").clicked() {
+                editing.status = match save(editing, &originals) {
+                    Ok(()) => format!("Saved {}", editing.path),
+                    Err(err) => err.to_string(),
+                };
+            }
+            if ui.button("Load").clicked() {
+                editing.status = match load(editing, &mut originals) {
+                    Ok(()) => format!("Loaded {}", editing.path),
+                    Err(err) => err.to_string(),
+                };
+            }
+        });
+        if !editing.status.is_empty() {
+            ui.label(&editing.status);
+        }
+    });
+}
+
+fn save(
+    editing: &Editing,
+    originals: &Query<(&mut Grid, &DatasetIdx), With<OriginalGrid>>,
+) -> Result<()> {
+    for (grid, idx) in originals {
+        if *idx == editing.dataset {
+            grid.save_buildings(&editing.path)?;
+        }
+    }
+    Ok(())
+}
+
+// Loading is one more stroke, so it can be undone
+fn load(
+    editing: &mut Editing,
+    originals: &mut Query<(&mut Grid, &DatasetIdx), With<OriginalGrid>>,
+) -> Result<()> {
+    for (mut grid, idx) in originals {
+        if *idx != editing.dataset {
+            continue;
+        }
+        let rows = grid.read_buildings(&editing.path)?;
+        let mut stroke = Stroke {
+            dataset: *idx,
+            cells: Vec::new(),
+        };
+        for (y, row) in rows.into_iter().enumerate() {
+            for (x, building) in row.into_iter().enumerate() {
+                if grid.is_building(x, y) != building {
+                    stroke.cells.push((x, y, !building));
+                    grid.set_building(x, y, building);
+                }
+            }
+        }
+        if !stroke.cells.is_empty() {
+            editing.undo.push(stroke);
+            editing.redo.clear();
+        }
+    }
+    Ok(())
+}
@@ -1,15 +1,54 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::ops::Range;
+
+use anyhow::{bail, Result};
 use bevy::prelude::{Color, Component, Transform, Vec2, Vec3};
 use bevy_prototype_lyon::entity::ShapeBundle;
 use bevy_prototype_lyon::prelude::{DrawMode, FillMode, GeometryBuilder};
 use bevy_prototype_lyon::shapes;
-use geo::{Contains, Point, Polygon, Rect};
+use geo::{
+    BoundingRect, Contains, Coord, EuclideanDistance, EuclideanLength, Geometry, Intersects, Line,
+    LinesIter, Point, Polygon, Rect,
+};
+use serde::{Deserialize, Serialize};
 
 #[derive(Clone, Component)]
 pub struct Grid {
     // Keep in mind this is row-major, (y, x)
     inner: grid::Grid<Cell>,
+    /// The corner of the first cell, in meters
+    origin: Coord,
     resolution_meters: f64,
     flood_frontier: Vec<(usize, usize)>,
+    /// Which step of the flood reached each cell
+    arrival: grid::Grid<Option<usize>>,
+    /// How many steps the flood has spread so far
+    step: usize,
+    /// Where each flood started, and at which step
+    seeds: Vec<FloodSeed>,
+    /// Which seed's flood reached each cell first, indexing `seeds`
+    reached_from: grid::Grid<Option<usize>>,
+}
+
+/// Where a flood started: one cell, or many along a line or outline
+#[derive(Clone)]
+pub struct FloodSeed {
+    pub cells: Vec<(usize, usize)>,
+    /// The step the flood was at when this started
+    pub step: usize,
+    /// Seeds with the same name count as one source, like every entrance to a station
+    pub source: Option<String>,
+}
+
+/// Where a grid is and how it's divided, to check a saved grid matches
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+pub struct GridParams {
+    pub cols: usize,
+    pub rows: usize,
+    /// The corner of the first cell, in meters
+    pub origin_x: f64,
+    pub origin_y: f64,
+    pub resolution_meters: f64,
 }
 
 #[derive(Clone, PartialEq)]
@@ -23,14 +62,17 @@ enum Cell {
 impl Grid {
     pub fn from_polygons(polygons: &[Polygon], bbox: Rect) -> Self {
         let resolution_meters = 10.0;
+        let rows = (bbox.height() / resolution_meters).ceil() as usize;
+        let cols = (bbox.width() / resolution_meters).ceil() as usize;
         let mut grid = Self {
-            inner: grid::Grid::init(
-                (bbox.height() / resolution_meters).ceil() as usize,
-                (bbox.width() / resolution_meters).ceil() as usize,
-                Cell::Empty,
-            ),
+            inner: grid::Grid::init(rows, cols, Cell::Empty),
+            origin: bbox.min(),
             resolution_meters,
             flood_frontier: Vec::new(),
+            arrival: grid::Grid::init(rows, cols, None),
+            step: 0,
+            seeds: Vec::new(),
+            reached_from: grid::Grid::init(rows, cols, None),
         };
 
         // TODO This is brute-force. Loop over each polygon, find the grid bbox, and fill out the
@@ -90,35 +132,406 @@ impl Grid {
         ]
     }
 
-    fn center_of_cell(&self, x: usize, y: usize) -> Point {
+    /// Draws cells that became buildings in one color, and cells that stopped being buildings in
+    /// another, between the original buildings and the flood
+    pub fn render_edits(&self, loaded: &Grid, painted: Color, erased: Color) -> Vec<ShapeBundle> {
+        let mut painted_builder = GeometryBuilder::new();
+        let mut erased_builder = GeometryBuilder::new();
+        for y in 0..self.inner.rows() {
+            for x in 0..self.inner.cols() {
+                let now = self.is_building(x, y);
+                if now == loaded.is_building(x, y) {
+                    continue;
+                }
+                let shape = shapes::Rectangle {
+                    extents: Vec2::splat(self.resolution_meters as f32),
+                    origin: shapes::RectangleOrigin::CustomCenter(pt_to_vec2(
+                        self.center_of_cell(x, y),
+                    )),
+                };
+                if now {
+                    painted_builder = painted_builder.add(&shape);
+                } else {
+                    erased_builder = erased_builder.add(&shape);
+                }
+            }
+        }
+
+        let transform = Transform::from_translation(Vec3::new(0.0, 0.0, 0.5));
+        vec![
+            painted_builder.build(DrawMode::Fill(FillMode::color(painted)), transform),
+            erased_builder.build(DrawMode::Fill(FillMode::color(erased)), transform),
+        ]
+    }
+
+    pub fn params(&self) -> GridParams {
+        GridParams {
+            cols: self.inner.cols(),
+            rows: self.inner.rows(),
+            origin_x: self.origin.x,
+            origin_y: self.origin.y,
+            resolution_meters: self.resolution_meters,
+        }
+    }
+
+    pub fn resolution_meters(&self) -> f64 {
+        self.resolution_meters
+    }
+
+    pub fn center_of_cell(&self, x: usize, y: usize) -> Point {
         Point::new(
-            (0.5 + (x as f64)) * self.resolution_meters,
-            (0.5 + (y as f64)) * self.resolution_meters,
+            self.origin.x + (0.5 + (x as f64)) * self.resolution_meters,
+            self.origin.y + (0.5 + (y as f64)) * self.resolution_meters,
         )
     }
 
     pub fn world_to_cell(&self, world_pt: Vec2) -> Option<(usize, usize)> {
-        if world_pt.x < 0.0 || world_pt.y < 0.0 {
+        self.coord_to_cell(Coord {
+            x: world_pt.x as f64,
+            y: world_pt.y as f64,
+        })
+    }
+
+    fn coord_to_cell(&self, c: Coord) -> Option<(usize, usize)> {
+        let x = c.x - self.origin.x;
+        let y = c.y - self.origin.y;
+        if x < 0.0 || y < 0.0 {
             return None;
         }
-        let x = (world_pt.x as f64 / self.resolution_meters).floor() as usize;
-        let y = (world_pt.y as f64 / self.resolution_meters).floor() as usize;
+        let x = (x / self.resolution_meters).floor() as usize;
+        let y = (y / self.resolution_meters).floor() as usize;
         if x >= self.inner.cols() || y >= self.inner.rows() {
             return None;
         }
         Some((x, y))
     }
 
-    pub fn start_flood(&mut self, x: usize, y: usize) {
-        self.flood_frontier.push((x, y));
+    /// The columns and rows of cells that could have their centers in a rectangle, clipped to the
+    /// grid
+    fn cell_range(&self, rect: Rect) -> (Range<usize>, Range<usize>) {
+        let to_index = |value: f64, limit: usize| {
+            ((value / self.resolution_meters).max(0.0) as usize).min(limit)
+        };
+        let xs = to_index(rect.min().x - self.origin.x, self.inner.cols())
+            ..to_index(
+                rect.max().x - self.origin.x + self.resolution_meters,
+                self.inner.cols(),
+            );
+        let ys = to_index(rect.min().y - self.origin.y, self.inner.rows())
+            ..to_index(
+                rect.max().y - self.origin.y + self.resolution_meters,
+                self.inner.rows(),
+            );
+        (xs, ys)
+    }
+
+    pub fn is_building(&self, x: usize, y: usize) -> bool {
+        self.inner[y][x] == Cell::Building
+    }
+
+    /// Only meant for grids that haven't flooded yet
+    pub fn set_building(&mut self, x: usize, y: usize, building: bool) {
+        self.inner[y][x] = if building {
+            Cell::Building
+        } else {
+            Cell::Empty
+        };
+    }
+
+    /// Cells touched by some geometry in meters: points, lines, and the outlines of polygons, plus
+    /// cells with centers within `reach` meters of those. Buildings and anything off the grid are
+    /// skipped.
+    pub fn cells_on(&self, geometry: &Geometry, reach: f64) -> Vec<(usize, usize)> {
+        let mut coords = Vec::new();
+        let lines: Vec<Line> = match geometry {
+            Geometry::Point(pt) => {
+                coords.push(pt.0);
+                Vec::new()
+            }
+            Geometry::MultiPoint(multi) => {
+                coords.extend(multi.iter().map(|pt| pt.0));
+                Vec::new()
+            }
+            Geometry::Line(line) => vec![*line],
+            Geometry::LineString(line) => line.lines_iter().collect(),
+            Geometry::MultiLineString(multi) => multi.lines_iter().collect(),
+            Geometry::Polygon(polygon) => polygon.lines_iter().collect(),
+            Geometry::MultiPolygon(multi) => multi.lines_iter().collect(),
+            Geometry::Rect(rect) => rect.lines_iter().collect(),
+            Geometry::Triangle(triangle) => triangle.lines_iter().collect(),
+            Geometry::GeometryCollection(collection) => {
+                let cells: BTreeSet<(usize, usize)> = collection
+                    .iter()
+                    .flat_map(|g| self.cells_on(g, reach))
+                    .collect();
+                return cells.into_iter().collect();
+            }
+        };
+        // Check often enough not to skip any cell along the way
+        for line in lines {
+            let samples = (2.0 * line.euclidean_length() / self.resolution_meters).ceil() as usize;
+            for i in 0..=samples {
+                let t = i as f64 / samples.max(1) as f64;
+                coords.push(line.start + (line.end - line.start) * t);
+            }
+        }
+
+        let mut cells = BTreeSet::new();
+        for c in coords {
+            cells.extend(self.coord_to_cell(c));
+            if reach > 0.0 {
+                cells.extend(self.cells_near(Vec2::new(c.x as f32, c.y as f32), reach));
+            }
+        }
+        cells
+            .into_iter()
+            .filter(|(x, y)| !self.is_building(*x, *y))
+            .collect()
+    }
+
+    /// Every cell around the edge of the grid, except buildings
+    pub fn edge_cells(&self) -> Vec<(usize, usize)> {
+        let (cols, rows) = (self.inner.cols(), self.inner.rows());
+        // A grid over a point or a line has no cells
+        if cols == 0 || rows == 0 {
+            return Vec::new();
+        }
+        let mut cells = BTreeSet::new();
+        for x in 0..cols {
+            cells.insert((x, 0));
+            cells.insert((x, rows - 1));
+        }
+        for y in 0..rows {
+            cells.insert((0, y));
+            cells.insert((cols - 1, y));
+        }
+        cells
+            .into_iter()
+            .filter(|(x, y)| !self.is_building(*x, *y))
+            .collect()
+    }
+
+    /// Redoes which cells are buildings, for cells with centers inside a region. Only meant for
+    /// grids that haven't flooded yet. Parts of the region off the grid are ignored.
+    pub fn rasterize(&mut self, polygons: &[Polygon], region: Rect) {
+        let polygons: Vec<&Polygon> = polygons
+            .iter()
+            .filter(|polygon| {
+                polygon
+                    .bounding_rect()
+                    .is_some_and(|bbox| bbox.intersects(&region))
+            })
+            .collect();
+        let (xs, ys) = self.cell_range(region);
+        for y in ys {
+            for x in xs.clone() {
+                let pt = self.center_of_cell(x, y);
+                if region.contains(&pt) {
+                    let building = polygons.iter().any(|polygon| polygon.contains(&pt));
+                    self.set_building(x, y, building);
+                }
+            }
+        }
+    }
+
+    /// Every cell with its center within some meters of a point
+    pub fn cells_near(&self, pt: Vec2, radius: f64) -> Vec<(usize, usize)> {
+        let center = Point::new(pt.x as f64, pt.y as f64);
+        let (xs, ys) = self.cell_range(Rect::new(
+            (center.x() - radius, center.y() - radius),
+            (center.x() + radius, center.y() + radius),
+        ));
+
+        let mut cells = Vec::new();
+        for y in ys {
+            for x in xs.clone() {
+                if self.center_of_cell(x, y).euclidean_distance(&center) <= radius {
+                    cells.push((x, y));
+                }
+            }
+        }
+        cells
+    }
+
+    /// Writes which cells are buildings as an ESRI ASCII grid, in meters
+    pub fn save_buildings(&self, path: &str) -> Result<()> {
+        let mut out = format!(
+            "ncols {}\nnrows {}\nxllcorner {}\nyllcorner {}\ncellsize {}\n",
+            self.inner.cols(),
+            self.inner.rows(),
+            self.origin.x,
+            self.origin.y,
+            self.resolution_meters
+        );
+        // The first row is the northernmost
+        for y in (0..self.inner.rows()).rev() {
+            let row: Vec<&str> = (0..self.inner.cols())
+                .map(|x| if self.is_building(x, y) { "1" } else { "0" })
+                .collect();
+            out.push_str(&row.join(" "));
+            out.push('\n');
+        }
+        std::fs::write(path, out)?;
+        Ok(())
+    }
+
+    /// Reads a file written by `save_buildings` for the same input, returning whether each cell is
+    /// a building, row by row
+    pub fn read_buildings(&self, path: &str) -> Result<Vec<Vec<bool>>> {
+        let contents = std::fs::read_to_string(path)?;
+        let mut lines = contents.lines();
+        let mut header = BTreeMap::new();
+        for _ in 0..5 {
+            let line = lines.next().unwrap_or_default();
+            match line.split_once(' ') {
+                Some((key, value)) => {
+                    header.insert(key.to_lowercase(), value.trim().parse::<f64>()?);
+                }
+                None => bail!("{path} has a bad header line: {line}"),
+            }
+        }
+        let expected = [
+            ("ncols", self.inner.cols() as f64),
+            ("nrows", self.inner.rows() as f64),
+            ("xllcorner", self.origin.x),
+            ("yllcorner", self.origin.y),
+            ("cellsize", self.resolution_meters),
+        ];
+        for (key, value) in expected {
+            if header.get(key).is_none_or(|x| (x - value).abs() > 0.01) {
+                bail!("{path} is for a different grid; {key} should be {value}");
+            }
+        }
+
+        let mut rows = Vec::new();
+        for line in lines {
+            let row = line
+                .split_whitespace()
+                .map(|x| x == "1")
+                .collect::<Vec<_>>();
+            if row.len() != self.inner.cols() {
+                bail!("{path} has a row with {} cells", row.len());
+            }
+            rows.push(row);
+        }
+        if rows.len() != self.inner.rows() {
+            bail!("{path} has {} rows", rows.len());
+        }
+        rows.reverse();
+        Ok(rows)
+    }
+
+    /// Where each flood started, and at which step
+    pub fn seeds(&self) -> &[FloodSeed] {
+        &self.seeds
+    }
+
+    /// How many steps the flood has spread so far
+    pub fn step(&self) -> usize {
+        self.step
+    }
+
+    /// Floods from some seeds, each starting at its own step, for a number of steps. Replaying
+    /// another grid's `seeds` and `step` after editing the buildings shows how its flood would've
+    /// gone instead.
+    pub fn replay_flood(&mut self, seeds: &[FloodSeed], steps: usize) {
+        let mut seeds = seeds.to_vec();
+        seeds.sort_by_key(|seed| seed.step);
+        let mut seeds = seeds.into_iter().peekable();
+        loop {
+            while let Some(seed) = seeds.next_if(|seed| seed.step <= self.step) {
+                self.start_flood(seed.cells, seed.source);
+            }
+            if self.step >= steps {
+                break;
+            }
+            if self.flood_frontier.is_empty() {
+                // The flood died out sooner this time, so skip ahead to the next seed
+                match seeds.peek() {
+                    Some(seed) => self.step = seed.step,
+                    None => break,
+                }
+            } else {
+                self.flood();
+            }
+        }
+    }
+
+    /// Describes what's in a cell, for people
+    pub fn cell_state(&self, x: usize, y: usize) -> &'static str {
+        match self.inner[y][x] {
+            Cell::Empty => "empty",
+            Cell::Building => "building",
+            Cell::Frontier => "flood frontier",
+            Cell::Flooded => "flooded",
+        }
+    }
+
+    /// Which step of the flood reached a cell, if it has yet
+    pub fn arrival_step(&self, x: usize, y: usize) -> Option<usize> {
+        self.arrival[y][x]
+    }
+
+    /// Which seed's flood reached a cell first, if any has yet
+    pub fn reached_from(&self, x: usize, y: usize) -> Option<&FloodSeed> {
+        self.reached_from[y][x].map(|idx| &self.seeds[idx])
+    }
+
+    /// The fraction of cells inside a polygon that are buildings, judging each cell by its
+    /// center. None if the polygon doesn't cover any cell centers.
+    pub fn building_fraction(&self, polygon: &Polygon) -> Option<f64> {
+        let bbox = polygon.bounding_rect()?;
+        let (xs, ys) = self.cell_range(bbox);
+
+        let mut cells = 0;
+        let mut buildings = 0;
+        for y in ys {
+            for x in xs.clone() {
+                if polygon.contains(&self.center_of_cell(x, y)) {
+                    cells += 1;
+                    if self.inner[y][x] == Cell::Building {
+                        buildings += 1;
+                    }
+                }
+            }
+        }
+        (cells > 0).then(|| buildings as f64 / cells as f64)
+    }
+
+    /// Floods from a set of cells at once, as one seed. Nothing happens with no cells. Cells that
+    /// are buildings, like when one was painted over a seed being replayed, don't flood, but stay
+    /// in the seed in case the building is erased again.
+    pub fn start_flood(&mut self, cells: Vec<(usize, usize)>, source: Option<String>) {
+        if cells.is_empty() {
+            return;
+        }
+        for (x, y) in &cells {
+            if self.is_building(*x, *y) {
+                continue;
+            }
+            self.reached_from[*y][*x].get_or_insert(self.seeds.len());
+            self.arrival[*y][*x].get_or_insert(self.step);
+            self.flood_frontier.push((*x, *y));
+        }
+        self.seeds.push(FloodSeed {
+            cells,
+            step: self.step,
+            source,
+        });
     }
 
     pub fn flood(&mut self) {
+        if self.flood_frontier.is_empty() {
+            return;
+        }
+        self.step += 1;
         let mut next = Vec::new();
         for (x, y) in &self.flood_frontier {
             self.inner[*y][*x] = Cell::Flooded;
         }
         for (x, y) in self.flood_frontier.drain(..) {
+            // Spreads the same source
+            let from = self.reached_from[y][x];
             let x = x as isize;
             let y = y as isize;
 
@@ -134,6 +547,8 @@ impl Grid {
                     }
                     if self.inner[y][x] == Cell::Empty {
                         self.inner[y][x] = Cell::Frontier;
+                        self.arrival[y][x] = Some(self.step);
+                        self.reached_from[y][x] = from;
                         next.push((x, y));
                     }
                 }
@@ -146,3 +561,92 @@ impl Grid {
 fn pt_to_vec2(pt: Point) -> Vec2 {
     Vec2::new(pt.x() as f32, pt.y() as f32)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 10 by 10 cells, with a building in the middle
+    fn grid() -> Grid {
+        Grid::from_polygons(
+            &[Rect::new((30.0, 30.0), (70.0, 70.0)).to_polygon()],
+            Rect::new((0.0, 0.0), (100.0, 100.0)),
+        )
+    }
+
+    fn arrivals(grid: &Grid) -> Vec<Option<usize>> {
+        grid.arrival.iter().copied().collect()
+    }
+
+    #[test]
+    fn replay_flood_matches_the_original_flood() {
+        let mut flooded = grid();
+        flooded.start_flood(vec![(0, 0)], None);
+        for _ in 0..2 {
+            flooded.flood();
+        }
+        flooded.start_flood(vec![(9, 9)], Some("second".to_string()));
+        for _ in 0..3 {
+            flooded.flood();
+        }
+
+        let mut replayed = grid();
+        replayed.replay_flood(flooded.seeds(), flooded.step());
+        assert_eq!(replayed.step(), flooded.step());
+        assert_eq!(arrivals(&replayed), arrivals(&flooded));
+        assert_eq!(
+            replayed.reached_from(9, 8).unwrap().source.as_deref(),
+            Some("second")
+        );
+    }
+
+    #[test]
+    fn replay_flood_skips_seeds_under_new_buildings() {
+        let mut flooded = grid();
+        flooded.start_flood(vec![(0, 0)], None);
+        flooded.flood();
+
+        let mut replayed = grid();
+        replayed.set_building(0, 0, true);
+        replayed.replay_flood(flooded.seeds(), flooded.step());
+        assert!(replayed.is_building(0, 0));
+        assert_eq!(replayed.arrival_step(0, 0), None);
+        assert_eq!(replayed.seeds().len(), 1);
+    }
+
+    #[test]
+    fn saved_buildings_read_back() {
+        let grid = grid();
+        let path = std::env::temp_dir().join("nolli_saved_buildings_read_back.asc");
+        let path = path.to_str().unwrap();
+        grid.save_buildings(path).unwrap();
+        let rows = grid.read_buildings(path).unwrap();
+        std::fs::remove_file(path).unwrap();
+
+        for (y, row) in rows.iter().enumerate() {
+            for (x, building) in row.iter().enumerate() {
+                assert_eq!(*building, grid.is_building(x, y));
+            }
+        }
+        assert!(rows[5][5]);
+        assert!(!rows[0][5]);
+    }
+
+    #[test]
+    fn reading_buildings_for_another_grid_fails() {
+        let path = std::env::temp_dir().join("nolli_reading_buildings_for_another_grid.asc");
+        let path = path.to_str().unwrap();
+        grid().save_buildings(path).unwrap();
+        let other = Grid::from_polygons(&[], Rect::new((0.0, 0.0), (50.0, 100.0)));
+        let result = other.read_buildings(path);
+        std::fs::remove_file(path).unwrap();
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn empty_grids_have_no_edge() {
+        let line = Grid::from_polygons(&[], Rect::new((0.0, 0.0), (100.0, 0.0)));
+        assert!(line.edge_cells().is_empty());
+        assert_eq!(grid().edge_cells().len(), 36);
+    }
+}
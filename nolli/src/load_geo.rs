@@ -1,38 +1,242 @@
-use anyhow::Result;
-use bevy::prelude::Mesh;
+use std::collections::BTreeMap;
+
+use anyhow::{bail, Result};
+use bevy::prelude::{Component, Mesh, Resource};
+use bevy::render::view::RenderLayers;
 use bevy_earcutr::{EarcutrInput, PolygonMeshBuilder};
+use common::projection::Projection;
 use geo::{
-    BoundingRect, Geometry, GeometryCollection, HaversineDistance, MapCoordsInPlace, Point,
-    Polygon, Rect,
+    BoundingRect, Geometry, Intersects, MapCoords, MapCoordsInPlace, MultiPolygon, Polygon, Rect,
 };
-use geojson::GeoJson;
+use geojson::feature::Id;
+use geojson::{Feature, FeatureCollection, GeoJson, JsonObject};
+
+pub struct Building {
+    /// In meters, from the corner of the projection
+    pub polygon: Polygon,
+    pub properties: JsonObject,
+    /// Which feature in the file this came from, and that feature's ID. Every part of a
+    /// multipolygon shares them. Drawn buildings have neither.
+    pub feature: Option<usize>,
+    pub id: Option<Id>,
+}
+
+/// One loaded GeoJSON file
+pub struct Dataset {
+    pub path: String,
+    pub buildings: Vec<Building>,
+    /// In meters
+    pub bbox: Rect,
+    pub projection: Projection,
+    /// Features that aren't buildings, still in WGS84, written back unchanged on export
+    pub other_features: Vec<OtherFeature>,
+    /// Buildings were drawn, moved, or deleted
+    pub edited: bool,
+}
+
+/// A feature that isn't a building, with its index in the file
+pub type OtherFeature = (usize, Feature);
+
+/// Everything loaded, shown side by side
+#[derive(Resource)]
+pub struct Datasets(pub Vec<Dataset>);
+
+/// Which dataset an entity belongs to. Each is drawn on the render layer with the same number.
+#[derive(Component, Clone, Copy, PartialEq, Eq, Default, Debug)]
+pub struct DatasetIdx(pub usize);
 
-/// Load polygons from a GeoJSON file and transform to Mercator
-pub fn load_buildings(path: &str) -> Result<(Vec<Polygon>, Rect)> {
+impl DatasetIdx {
+    pub fn layer(self) -> RenderLayers {
+        RenderLayers::layer(self.0 as u8)
+    }
+}
+
+/// Load polygons from a GeoJSON file and transform to Mercator. If the file covers some of the
+/// same place as `previous`, share its projection, so the two line up.
+pub fn load_buildings(path: &str, previous: Option<&Dataset>) -> Result<Dataset> {
     let geojson = std::fs::read_to_string(path)?.parse::<GeoJson>()?;
-    let mut collection: GeometryCollection<f64> = geojson::quick_collection(&geojson)?;
-
-    // Filter out non-polygons
-    collection
-        .0
-        .retain(|geom| matches!(geom, Geometry::Polygon(_)));
-
-    let top_left: Point = collection.bounding_rect().unwrap().min().into();
-
-    collection.map_coords_in_place(|c| {
-        let x = Point::new(c.x, top_left.y()).haversine_distance(&top_left);
-        let y = Point::new(top_left.x(), c.y).haversine_distance(&top_left);
-        (x, y).into()
-    });
-    let bbox = collection.bounding_rect().unwrap();
-
-    let mut polygons = Vec::new();
-    for geom in collection {
-        if let Geometry::Polygon(polygon) = geom {
-            polygons.push(polygon);
+    let (mut buildings, other_features) = read_polygons(geojson)?;
+    if buildings.is_empty() {
+        bail!("{path} doesn't contain any polygons");
+    }
+
+    let lonlat_bbox = bounding_rect(&buildings);
+    let projection = match previous {
+        Some(previous) if previous.lonlat_bbox().intersects(&lonlat_bbox) => previous.projection,
+        // Meters from the bottom-left of the first file
+        _ => Projection::new(lonlat_bbox.min()),
+    };
+    for building in &mut buildings {
+        building
+            .polygon
+            .map_coords_in_place(|c| projection.to_meters(c));
+    }
+    let bbox = bounding_rect(&buildings);
+
+    Ok(Dataset {
+        path: path.to_string(),
+        buildings,
+        bbox,
+        projection,
+        other_features,
+        edited: false,
+    })
+}
+
+// Polygons become buildings, keeping their properties, and each part of a multipolygon becomes
+// its own building. Returns every other feature separately. Still in WGS84.
+fn read_polygons(geojson: GeoJson) -> Result<(Vec<Building>, Vec<OtherFeature>)> {
+    let mut buildings = Vec::new();
+    let mut others = Vec::new();
+    for (idx, feature) in features(geojson).into_iter().enumerate() {
+        let geometry = match feature.geometry {
+            Some(ref geometry) => Geometry::try_from(geometry.clone())?,
+            None => {
+                others.push((idx, feature));
+                continue;
+            }
+        };
+        let polygons = match geometry {
+            Geometry::Polygon(polygon) => vec![polygon],
+            Geometry::MultiPolygon(multi) => multi.0,
+            _ => {
+                others.push((idx, feature));
+                continue;
+            }
+        };
+        let properties = feature.properties.unwrap_or_default();
+        for polygon in polygons {
+            buildings.push(Building {
+                polygon,
+                properties: properties.clone(),
+                feature: Some(idx),
+                id: feature.id.clone(),
+            });
+        }
+    }
+    Ok((buildings, others))
+}
+
+/// Load every feature's geometry from a GeoJSON file, still in WGS84. If `name_property` is given,
+/// each is named by that property's value.
+pub fn load_geometries(
+    path: &str,
+    name_property: Option<&str>,
+) -> Result<Vec<(Geometry, Option<String>)>> {
+    let geojson = std::fs::read_to_string(path)?.parse::<GeoJson>()?;
+    let mut geometries = Vec::new();
+    for feature in features(geojson) {
+        let name = name_property
+            .and_then(|key| feature.property(key))
+            .map(|value| match value.as_str() {
+                Some(x) => x.to_string(),
+                None => value.to_string(),
+            });
+        if let Some(ref geometry) = feature.geometry {
+            geometries.push((Geometry::try_from(geometry.clone())?, name));
         }
     }
-    Ok((polygons, bbox))
+    if geometries.is_empty() {
+        bail!("{path} doesn't contain anything");
+    }
+    Ok(geometries)
+}
+
+fn features(geojson: GeoJson) -> Vec<Feature> {
+    match geojson {
+        GeoJson::FeatureCollection(collection) => collection.features,
+        GeoJson::Feature(feature) => vec![feature],
+        GeoJson::Geometry(geometry) => vec![Feature::from(geometry)],
+    }
+}
+
+impl Dataset {
+    /// Writes every feature back to GeoJSON in WGS84, in the same order, followed by drawn
+    /// buildings
+    pub fn save_geojson(&self, path: &str) -> Result<()> {
+        std::fs::write(path, self.to_geojson().to_string())?;
+        Ok(())
+    }
+
+    pub fn to_geojson(&self) -> GeoJson {
+        // Parts of a multipolygon go back together
+        let mut from_file: BTreeMap<usize, Vec<&Building>> = BTreeMap::new();
+        let mut drawn = Vec::new();
+        for building in &self.buildings {
+            match building.feature {
+                Some(idx) => from_file.entry(idx).or_default().push(building),
+                None => drawn.push(vec![building]),
+            }
+        }
+        let mut features: Vec<(usize, Feature)> = from_file
+            .into_iter()
+            .map(|(idx, parts)| (idx, self.to_feature(&parts)))
+            .chain(self.other_features.iter().cloned())
+            .collect();
+        features.sort_by_key(|(idx, _)| *idx);
+
+        GeoJson::FeatureCollection(FeatureCollection {
+            bbox: None,
+            features: features
+                .into_iter()
+                .map(|(_, feature)| feature)
+                .chain(drawn.iter().map(|parts| self.to_feature(parts)))
+                .collect(),
+            foreign_members: None,
+        })
+    }
+
+    // One polygon, or a multipolygon with many parts
+    fn to_feature(&self, parts: &[&Building]) -> Feature {
+        let mut polygons: Vec<Polygon> = parts
+            .iter()
+            .map(|building| {
+                building
+                    .polygon
+                    .map_coords(|c| self.projection.to_lonlat(c))
+            })
+            .collect();
+        let value = if polygons.len() == 1 {
+            geojson::Value::from(&polygons.pop().unwrap())
+        } else {
+            geojson::Value::from(&MultiPolygon::new(polygons))
+        };
+        Feature {
+            bbox: None,
+            geometry: Some(geojson::Geometry::new(value)),
+            id: parts[0].id.clone(),
+            properties: Some(parts[0].properties.clone()),
+            foreign_members: None,
+        }
+    }
+
+    /// Swaps in buildings from `to_geojson`, keeping the same projection and bounding box, so the
+    /// grid doesn't change
+    pub fn replace_buildings(&mut self, geojson: GeoJson) -> Result<()> {
+        let (mut buildings, other_features) = read_polygons(geojson)?;
+        for building in &mut buildings {
+            building
+                .polygon
+                .map_coords_in_place(|c| self.projection.to_meters(c));
+        }
+        self.buildings = buildings;
+        self.other_features = other_features;
+        self.edited = true;
+        Ok(())
+    }
+
+    fn lonlat_bbox(&self) -> Rect {
+        Rect::new(
+            self.projection.to_lonlat(self.bbox.min()),
+            self.projection.to_lonlat(self.bbox.max()),
+        )
+    }
+}
+
+fn bounding_rect(buildings: &[Building]) -> Rect {
+    MultiPolygon::new(buildings.iter().map(|b| b.polygon.clone()).collect())
+        .bounding_rect()
+        .unwrap()
 }
 
 pub fn polygons_to_mesh(polygons: Vec<Polygon>) -> Mesh {
@@ -49,3 +253,109 @@ pub fn polygons_to_mesh(polygons: Vec<Polygon>) -> Mesh {
     }
     builder.build().unwrap()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A square of buildings in longitude and latitude, written to a temporary file
+    fn write_square(name: &str, lon: f64, lat: f64) -> String {
+        let path = std::env::temp_dir().join(format!("nolli_{name}.geojson"));
+        let (x1, y1, x2, y2) = (lon, lat, lon + 0.001, lat + 0.001);
+        std::fs::write(
+            &path,
+            format!(
+                r#"{{"type": "Feature", "properties": {{}}, "geometry": {{"type": "Polygon",
+                    "coordinates": [[[{x1}, {y1}], [{x2}, {y1}], [{x2}, {y2}], [{x1}, {y2}],
+                    [{x1}, {y1}]]]}}}}"#
+            ),
+        )
+        .unwrap();
+        path.to_str().unwrap().to_string()
+    }
+
+    #[test]
+    fn overlapping_files_share_a_projection() {
+        let paths = [
+            write_square("overlapping_first", 12.48, 41.89),
+            write_square("overlapping_second", 12.4805, 41.8905),
+            write_square("overlapping_elsewhere", 2.35, 48.85),
+        ];
+        let first = load_buildings(&paths[0], None).unwrap();
+        let overlapping = load_buildings(&paths[1], Some(&first)).unwrap();
+        let elsewhere = load_buildings(&paths[2], Some(&first)).unwrap();
+        for path in paths {
+            std::fs::remove_file(path).unwrap();
+        }
+
+        // Half of the second square lines up with the first
+        assert_eq!(overlapping.projection, first.projection);
+        assert!((overlapping.bbox.min().x - first.bbox.center().x).abs() < 1.0);
+        // Somewhere else starts from its own corner
+        assert_ne!(elsewhere.projection, first.projection);
+        assert!(elsewhere.bbox.min().x.abs() < 1e-6);
+    }
+
+    #[test]
+    fn export_round_trips_features() {
+        let path = std::env::temp_dir().join("nolli_export_round_trips_features.geojson");
+        let path = path.to_str().unwrap();
+        let square = |x: f64| {
+            let (x1, x2, y1, y2) = (12.48 + x, 12.481 + x, 41.89, 41.891);
+            format!("[[[{x1}, {y1}], [{x2}, {y1}], [{x2}, {y2}], [{x1}, {y2}], [{x1}, {y1}]]]")
+        };
+        std::fs::write(
+            path,
+            format!(
+                r#"{{"type": "FeatureCollection", "features": [
+                    {{"type": "Feature", "id": "way/1", "properties": {{"name": "one"}},
+                      "geometry": {{"type": "Polygon", "coordinates": {}}}}},
+                    {{"type": "Feature", "properties": {{"name": "tree"}},
+                      "geometry": {{"type": "Point", "coordinates": [12.48, 41.89]}}}},
+                    {{"type": "Feature", "id": 7, "properties": {{"name": "pair"}},
+                      "geometry": {{"type": "MultiPolygon", "coordinates": [{}, {}]}}}}
+                ]}}"#,
+                square(0.0),
+                square(0.002),
+                square(0.004)
+            ),
+        )
+        .unwrap();
+
+        let mut dataset = load_buildings(path, None).unwrap();
+        assert_eq!(dataset.buildings.len(), 3);
+        dataset.buildings.push(Building {
+            polygon: dataset.buildings[0].polygon.clone(),
+            properties: JsonObject::new(),
+            feature: None,
+            id: None,
+        });
+        dataset.save_geojson(path).unwrap();
+        let saved = std::fs::read_to_string(path).unwrap().parse::<GeoJson>();
+        let reloaded = load_buildings(path, None).unwrap();
+        std::fs::remove_file(path).unwrap();
+
+        let features = features(saved.unwrap());
+        assert_eq!(features.len(), 4);
+        let ids: Vec<Option<Id>> = features.iter().map(|f| f.id.clone()).collect();
+        assert_eq!(
+            ids,
+            vec![
+                Some(Id::String("way/1".to_string())),
+                None,
+                Some(Id::Number(7.into())),
+                None
+            ]
+        );
+        let names: Vec<Option<&str>> = features
+            .iter()
+            .map(|f| f.property("name").and_then(|name| name.as_str()))
+            .collect();
+        assert_eq!(names, vec![Some("one"), Some("tree"), Some("pair"), None]);
+        assert!(matches!(
+            features[2].geometry.as_ref().unwrap().value,
+            geojson::Value::MultiPolygon(ref parts) if parts.len() == 2
+        ));
+        assert_eq!(reloaded.buildings.len(), 4);
+    }
+}
@@ -1,47 +1,184 @@
 use anyhow::Result;
+use bevy::core_pipeline::clear_color::ClearColorConfig;
 use bevy::prelude::{
-    default, App, Assets, Camera2dBundle, Color, ColorMaterial, Commands, Component,
-    DefaultPlugins, Entity, Input, KeyCode, Mesh, ParamSet, Query, Res, ResMut, Resource,
-    SystemSet, With,
+    default, App, Assets, Camera, Camera2d, Camera2dBundle, Changed, Color, ColorMaterial,
+    Commands, Component, CoreStage, DefaultPlugins, Entity, Input, IntoSystemDescriptor, KeyCode,
+    Mesh, OrthographicProjection, ParamSet, Query, Res, ResMut, Resource, StartupStage, SystemSet,
+    Transform, Vec2, Windows, With,
 };
 use bevy::sprite::MaterialMesh2dBundle;
 use bevy::time::FixedTimestep;
+use bevy::winit::WinitSettings;
 use bevy_egui::{egui, EguiContext};
+use bevy_image_export::ImageExportPlugin;
 use bevy_inspector_egui::WorldInspectorPlugin;
 use bevy_pancam::{PanCam, PanCamPlugin};
 use bevy_prototype_lyon::prelude::ShapePlugin;
+use common::args::CmdArgs;
+use common::capture::Capture;
+use geo::{Coord, Polygon};
 
+use self::camera::LinkedCamera;
 use self::cursor_worldspace::CursorWorldspace;
+use self::draw::{BuildingsChanged, Drawing};
+use self::edit::Editing;
 use self::grid::Grid;
+use self::load_geo::{DatasetIdx, Datasets};
+use self::measure::Measurement;
+use self::seeds::SeedsFile;
+use self::selection::Selection;
+use self::session::{Session, SessionFile};
 
+mod camera;
+mod capture;
 mod cursor_worldspace;
+mod draw;
+mod edit;
 mod grid;
 mod load_geo;
+mod measure;
+mod minimap;
+mod seeds;
+mod selection;
+mod session;
 
 fn main() -> Result<()> {
-    App::new()
+    let mut args = CmdArgs::new();
+    let session_path = args.optional("--session");
+    let session = match session_path {
+        Some(ref path) => Some(Session::load(path)?),
+        None => None,
+    };
+    // A session says which files to load
+    let mut paths = Vec::new();
+    if session.is_none() {
+        paths.push(args.required_free());
+        // A second file is shown side by side
+        paths.extend(args.optional_free());
+    }
+    let seeds_file = SeedsFile::new(args.optional("--seeds"), args.optional("--group-seeds-by"))?;
+    let capture = Capture::from_args(&mut args)?;
+    args.done();
+
+    let datasets = match session {
+        Some(ref session) => session.load_datasets()?,
+        None => {
+            let mut datasets = vec![load_geo::load_buildings(&paths[0], None)?];
+            if let Some(path) = paths.get(1) {
+                datasets.push(load_geo::load_buildings(path, datasets.first())?);
+            }
+            datasets
+        }
+    };
+
+    let export_plugin = ImageExportPlugin::default();
+    let export_threads = export_plugin.threads.clone();
+
+    let mut app = App::new();
+    app.insert_resource(Datasets(datasets))
+        // Return from run(), so frames still being saved can finish
+        .insert_resource(WinitSettings {
+            return_from_run: true,
+            ..default()
+        })
         .add_plugins(DefaultPlugins)
-        .add_plugin(PanCamPlugin::default())
+        .add_plugin(export_plugin)
+        .add_plugin(PanCamPlugin)
         .add_plugin(ShapePlugin)
         .add_plugin(WorldInspectorPlugin::new())
         .add_startup_system(setup)
         .add_system(key_controls)
+        .add_system(camera::zoom_to_extent_key)
+        .add_system(camera::link_cameras)
+        .add_startup_system(minimap::setup)
+        .add_system(minimap::update)
+        .add_system(minimap::controls.after(cursor_worldspace::cursor_to_world))
         .add_system_set(
             SystemSet::new()
                 .with_run_criteria(FixedTimestep::step(0.1))
                 .with_system(do_flood),
         )
+        .add_system(render_grids.after(edit::reflood))
         .init_resource::<CursorWorldspace>()
         .add_system(cursor_worldspace::cursor_to_world)
         .insert_resource(FloodState { paused: false })
         .add_system(flood_controls)
-        .run();
+        .add_system(hover_tooltip.after(cursor_worldspace::cursor_to_world))
+        .insert_resource(Tool::Select)
+        .init_resource::<Selection>()
+        .add_system(selection::select_on_click.after(cursor_worldspace::cursor_to_world))
+        .add_system(selection::highlight_selection.after(selection::select_on_click))
+        .add_system(selection::inspector)
+        .init_resource::<Measurement>()
+        .add_system(measure::measure_on_click.after(cursor_worldspace::cursor_to_world))
+        .add_system(measure::draw_measurement.after(measure::measure_on_click))
+        .add_system(measure::controls)
+        .init_resource::<Editing>()
+        .add_system(edit::paint.after(cursor_worldspace::cursor_to_world))
+        .add_system(edit::undo_keys)
+        .add_system(edit::controls)
+        .add_system(
+            edit::reflood
+                .after(edit::paint)
+                .after(edit::undo_keys)
+                .after(edit::controls),
+        )
+        .add_system(edit::render_edits.after(edit::reflood))
+        .add_system(edit::pan_buttons)
+        .add_event::<BuildingsChanged>()
+        .init_resource::<Drawing>()
+        .add_system(draw::draw_on_click.after(cursor_worldspace::cursor_to_world))
+        .add_system(draw::move_on_drag.after(cursor_worldspace::cursor_to_world))
+        .add_system(draw::delete_key)
+        .add_system(draw::controls)
+        .add_system(
+            draw::update_buildings
+                .after(draw::draw_on_click)
+                .after(draw::move_on_drag)
+                .after(draw::delete_key)
+                .after(draw::controls)
+                .after(selection::inspector)
+                .before(edit::reflood),
+        )
+        .add_system(
+            draw::draw_outlines
+                .after(draw::draw_on_click)
+                .after(draw::move_on_drag),
+        )
+        .insert_resource(capture)
+        .add_system(common::capture::toggle_key)
+        .add_system_to_stage(
+            CoreStage::Last,
+            capture::spawn_cameras.before(common::capture::capture_frames),
+        )
+        .add_system_to_stage(CoreStage::Last, common::capture::capture_frames)
+        .add_system_to_stage(
+            CoreStage::Last,
+            common::capture::follow_parent::<OrthographicProjection>,
+        )
+        .add_system_to_stage(CoreStage::Last, common::capture::share_images)
+        .insert_resource(SessionFile::new(
+            session_path.unwrap_or_else(|| "session.json".to_string()),
+        ))
+        // After setup makes the grids
+        .add_startup_system_to_stage(StartupStage::PostStartup, session::restore)
+        .add_system(session::controls)
+        .insert_resource(seeds_file)
+        .add_startup_system_to_stage(
+            StartupStage::PostStartup,
+            seeds::load_at_startup.after(session::restore),
+        )
+        .add_system(seeds::controls);
+    if let Some(session) = session {
+        app.insert_resource(session);
+    }
+    app.run();
+
+    export_threads.finish();
 
     Ok(())
 }
 
-// TODO These are all singletons. Should they just be a Resource?
-
 // Just taggging the ShapeBundles to change them later
 #[derive(Component)]
 struct RenderGrid;
@@ -49,73 +186,140 @@ struct RenderGrid;
 #[derive(Component)]
 struct ActiveGrid;
 
+/// The buildings flooding starts from, after any edits
 #[derive(Component)]
 struct OriginalGrid;
 
+/// The buildings as loaded from the file, before any edits
+#[derive(Component)]
+struct LoadedGrid;
+
+/// The mesh with every building in a dataset
+#[derive(Component)]
+struct BuildingsMesh;
+
 fn setup(
     mut commands: Commands,
     mut meshes: ResMut<Assets<Mesh>>,
     mut materials: ResMut<Assets<ColorMaterial>>,
+    datasets: Res<Datasets>,
+    windows: Res<Windows>,
 ) {
-    let mut args: Vec<String> = std::env::args().collect();
-    if args.len() != 2 {
-        panic!("Pass a path to a .geojson containing some polygons");
-    }
-    let path = args.pop().unwrap();
+    for (idx, dataset) in datasets.0.iter().enumerate() {
+        let idx = DatasetIdx(idx);
+        let polygons: Vec<Polygon> = dataset
+            .buildings
+            .iter()
+            .map(|b| b.polygon.clone())
+            .collect();
+        let grid = Grid::from_polygons(&polygons, dataset.bbox);
 
-    let (buildings, bbox) = load_geo::load_buildings(&path).unwrap();
-    let grid = Grid::from_polygons(&buildings, bbox);
+        for bundle in grid.render() {
+            commands.spawn((bundle, RenderGrid, idx, idx.layer()));
+        }
+        commands.spawn((grid.clone(), ActiveGrid, idx));
+        commands.spawn((grid.clone(), LoadedGrid, idx));
+        commands.spawn((grid, OriginalGrid, idx));
+        commands.spawn((
+            MaterialMesh2dBundle {
+                mesh: meshes.add(load_geo::polygons_to_mesh(polygons)).into(),
+                material: materials.add(ColorMaterial::from(Color::hex("601865").unwrap())),
+                ..default()
+            },
+            BuildingsMesh,
+            idx,
+            idx.layer(),
+        ));
+    }
 
-    for bundle in grid.render() {
-        commands.spawn((bundle, RenderGrid));
+    // Start looking at everything
+    let mut camera = Camera2dBundle::default();
+    if let Some(window) = windows.get_primary() {
+        camera::zoom_to_extent(
+            camera::full_extent(&datasets),
+            camera::viewport_size(&datasets, Vec2::new(window.width(), window.height())),
+            &mut camera.transform,
+            &mut camera.projection,
+        );
     }
-    commands.spawn((grid.clone(), ActiveGrid));
-    commands.spawn((grid, OriginalGrid));
-    commands.spawn(MaterialMesh2dBundle {
-        mesh: meshes.add(load_geo::polygons_to_mesh(buildings)).into(),
-        material: materials
-            .add(ColorMaterial::from(Color::hex("601865").unwrap()))
-            .into(),
-        ..default()
-    });
-    commands.spawn((Camera2dBundle::default(), PanCam::default()));
+    // Every other dataset gets a camera following the main one
+    for idx in 1..datasets.0.len() {
+        let idx = DatasetIdx(idx);
+        commands.spawn((
+            Camera2dBundle {
+                camera: Camera {
+                    priority: idx.0 as isize,
+                    ..default()
+                },
+                // The main camera clears the whole window, including this viewport. Clearing again
+                // would erase its view.
+                camera_2d: Camera2d {
+                    clear_color: ClearColorConfig::None,
+                },
+                transform: camera.transform,
+                projection: camera.projection.clone(),
+                ..default()
+            },
+            LinkedCamera,
+            idx,
+            idx.layer(),
+        ));
+    }
+    commands.spawn((camera, PanCam::default(), DatasetIdx(0)));
 }
 
 fn key_controls(
     keys: Res<Input<KeyCode>>,
     cursor: Res<CursorWorldspace>,
-    mut query: Query<&mut Grid, With<ActiveGrid>>,
+    cameras: Query<&DatasetIdx, With<Camera>>,
+    mut query: Query<(&mut Grid, &DatasetIdx), With<ActiveGrid>>,
 ) {
     if keys.just_pressed(KeyCode::Space) {
-        let mut grid = query.single_mut();
-        if let Some(pt) = cursor.0 {
-            if let Some((x, y)) = grid.world_to_cell(pt) {
-                println!("Starting flood from {x}, {y}");
-                grid.start_flood(x, y);
+        let dataset = if let Some(dataset) = cursor.dataset(&cameras) {
+            dataset
+        } else {
+            return;
+        };
+        for (mut grid, idx) in &mut query {
+            if *idx != dataset {
+                continue;
+            }
+            if let Some(pt) = cursor.pos {
+                if let Some((x, y)) = grid.world_to_cell(pt) {
+                    println!("Starting flood from {x}, {y}");
+                    grid.start_flood(vec![(x, y)], None);
+                }
             }
         }
     }
 }
 
-fn do_flood(
-    mut query1: Query<&mut Grid, With<ActiveGrid>>,
-    query2: Query<Entity, With<RenderGrid>>,
-    state: Res<FloodState>,
-    mut commands: Commands,
-) {
+fn do_flood(mut query: Query<&mut Grid, With<ActiveGrid>>, state: Res<FloodState>) {
     if state.paused {
         return;
     }
+    for mut grid in &mut query {
+        grid.flood();
+    }
+}
 
-    let mut grid = query1.single_mut();
-    grid.flood();
-
+/// Redraws the flood for every grid that changed
+#[allow(clippy::type_complexity)]
+fn render_grids(
+    query1: Query<(&Grid, &DatasetIdx), (With<ActiveGrid>, Changed<Grid>)>,
+    query2: Query<(Entity, &DatasetIdx), With<RenderGrid>>,
+    mut commands: Commands,
+) {
     // TODO This is definitely not the way to re-render
-    for entity in &query2 {
-        commands.entity(entity).despawn();
-    }
-    for bundle in grid.render() {
-        commands.spawn((bundle, RenderGrid));
+    for (grid, idx) in &query1 {
+        for (entity, render_idx) in &query2 {
+            if render_idx == idx {
+                commands.entity(entity).despawn();
+            }
+        }
+        for bundle in grid.render() {
+            commands.spawn((bundle, RenderGrid, *idx, idx.layer()));
+        }
     }
 }
 
@@ -124,21 +328,126 @@ struct FloodState {
     paused: bool,
 }
 
+/// What clicking on the map does
+#[derive(Resource, Clone, Copy, PartialEq)]
+pub enum Tool {
+    Select,
+    Measure,
+    Paint,
+    Erase,
+    Draw,
+    Move,
+}
+
+impl Tool {
+    /// These use the left mouse button for dragging, so it can't pan
+    pub fn drags(self) -> bool {
+        matches!(self, Tool::Paint | Tool::Erase | Tool::Move)
+    }
+}
+
+#[allow(clippy::type_complexity, clippy::too_many_arguments)]
 fn flood_controls(
     mut ctx: ResMut<EguiContext>,
     mut state: ResMut<FloodState>,
+    mut capture: ResMut<Capture>,
+    mut tool: ResMut<Tool>,
     mut set: ParamSet<(
-        Query<&mut Grid, With<ActiveGrid>>,
-        Query<&Grid, With<OriginalGrid>>,
+        Query<(&mut Grid, &DatasetIdx), With<ActiveGrid>>,
+        Query<(&Grid, &DatasetIdx), With<OriginalGrid>>,
     )>,
+    datasets: Res<Datasets>,
+    windows: Res<Windows>,
+    mut cameras: Query<(&mut Transform, &mut OrthographicProjection), With<PanCam>>,
 ) {
+    let mut new_tool = *tool;
     egui::Window::new("Controls").show(ctx.ctx_mut(), |ui| {
+        ui.horizontal(|ui| {
+            ui.label("Click to");
+            ui.radio_value(&mut new_tool, Tool::Select, "select");
+            ui.radio_value(&mut new_tool, Tool::Measure, "measure");
+            ui.radio_value(&mut new_tool, Tool::Paint, "paint");
+            ui.radio_value(&mut new_tool, Tool::Erase, "erase");
+            ui.radio_value(&mut new_tool, Tool::Draw, "draw");
+            ui.radio_value(&mut new_tool, Tool::Move, "move");
+        });
         if ui.button("Pause/resume").clicked() {
             state.paused = !state.paused;
         }
         if ui.button("Reset").clicked() {
-            *set.p0().single_mut() = set.p1().single().clone();
-            // TODO Re-render immediately, in case we're paused?
+            let originals: Vec<(Grid, DatasetIdx)> = set
+                .p1()
+                .iter()
+                .map(|(grid, idx)| (grid.clone(), *idx))
+                .collect();
+            for (mut grid, idx) in &mut set.p0() {
+                if let Some((original, _)) = originals.iter().find(|(_, i)| i == idx) {
+                    *grid = original.clone();
+                }
+            }
+        }
+        if ui.button("Flood inward from the edges").clicked() {
+            for (mut grid, _) in &mut set.p0() {
+                let cells = grid.edge_cells();
+                grid.start_flood(cells, Some("the edge".to_string()));
+            }
+        }
+        if ui.button("Zoom to extent (Home)").clicked() {
+            camera::zoom_main_camera(&datasets, &windows, &mut cameras);
+        }
+        common::capture::controls(ui, &mut capture);
+    });
+    if new_tool != *tool {
+        *tool = new_tool;
+    }
+}
+
+/// Describes whatever's under the cursor
+fn hover_tooltip(
+    mut ctx: ResMut<EguiContext>,
+    cursor: Res<CursorWorldspace>,
+    datasets: Res<Datasets>,
+    cameras: Query<&DatasetIdx, With<Camera>>,
+    query: Query<(&Grid, &DatasetIdx), With<ActiveGrid>>,
+) {
+    let (pt, dataset) = match (cursor.pos, cursor.dataset(&cameras)) {
+        (Some(pt), Some(dataset)) => (pt, dataset),
+        _ => return,
+    };
+    let ctx = ctx.ctx_mut();
+    if ctx.is_pointer_over_area() {
+        return;
+    }
+    let grid = if let Some((grid, _)) = query.iter().find(|(_, idx)| **idx == dataset) {
+        grid
+    } else {
+        return;
+    };
+    let lonlat = datasets.0[dataset.0].projection.to_lonlat(Coord {
+        x: pt.x as f64,
+        y: pt.y as f64,
+    });
+
+    egui::show_tooltip_at_pointer(ctx, egui::Id::new("hover"), |ui| {
+        if datasets.0.len() > 1 {
+            ui.label(&datasets.0[dataset.0].path);
+        }
+        if let Some((x, y)) = grid.world_to_cell(pt) {
+            ui.label(format!("Cell {x}, {y}: {}", grid.cell_state(x, y)));
+            match grid.arrival_step(x, y) {
+                Some(step) => ui.label(format!("Flood arrived at step {step}")),
+                None => ui.label("Not flooded"),
+            };
+            if let Some(source) = grid
+                .reached_from(x, y)
+                .and_then(|seed| seed.source.as_ref())
+            {
+                ui.label(format!("Reached from {source}"));
+            }
+        } else {
+            ui.label("Outside the grid");
         }
+        ui.label(format!("{:.1} m, {:.1} m", pt.x, pt.y));
+        ui.label(format!("{:.6}, {:.6}", lonlat.x, lonlat.y));
     });
 }
@@ -0,0 +1,256 @@
+use bevy::prelude::{
+    Camera, Color, Commands, Component, Entity, Input, KeyCode, Local, MouseButton, Query, Res,
+    ResMut, Resource, Transform, Vec2, Vec3, Windows, With,
+};
+use bevy_egui::{egui, EguiContext};
+use bevy_prototype_lyon::prelude::{DrawMode, GeometryBuilder, StrokeMode};
+use bevy_prototype_lyon::shapes;
+use geo::{Area, Coord, Geometry, LineString, Point, Polygon};
+
+use crate::cursor_worldspace::{ClickDetector, CursorWorldspace};
+use crate::grid::Grid;
+use crate::load_geo::DatasetIdx;
+use crate::{ActiveGrid, Tool};
+
+/// Points clicked with the measuring tool, in meters
+#[derive(Resource, Default)]
+pub struct Measurement {
+    /// Where the points are
+    dataset: DatasetIdx,
+    points: Vec<Vec2>,
+    /// Measure the enclosed area too
+    closed: bool,
+}
+
+impl Measurement {
+    fn segment_lengths(&self) -> impl Iterator<Item = f32> + '_ {
+        let closing = self
+            .closed
+            .then(|| (self.points[self.points.len() - 1], self.points[0]));
+        self.points
+            .windows(2)
+            .map(|pair| (pair[0], pair[1]))
+            .chain(closing)
+            .map(|(p1, p2)| p1.distance(p2))
+    }
+
+    fn polygon(&self) -> Option<Polygon> {
+        if !self.closed {
+            return None;
+        }
+        let ring: Vec<Coord> = self
+            .points
+            .iter()
+            .map(|pt| Coord {
+                x: pt.x as f64,
+                y: pt.y as f64,
+            })
+            .collect();
+        Some(Polygon::new(LineString::from(ring), Vec::new()))
+    }
+
+    /// The polygon, or the line or point if it's not closed
+    fn geometry(&self) -> Option<Geometry> {
+        if let Some(polygon) = self.polygon() {
+            return Some(Geometry::Polygon(polygon));
+        }
+        let coords: Vec<Coord> = self
+            .points
+            .iter()
+            .map(|pt| Coord {
+                x: pt.x as f64,
+                y: pt.y as f64,
+            })
+            .collect();
+        match coords.len() {
+            0 => None,
+            1 => Some(Geometry::Point(Point(coords[0]))),
+            _ => Some(Geometry::LineString(LineString::from(coords))),
+        }
+    }
+
+    fn clear(&mut self) {
+        self.points.clear();
+        self.closed = false;
+    }
+}
+
+/// Marks the lines drawn for the measurement
+#[derive(Component)]
+pub struct MeasurementShape;
+
+/// With the measuring tool, clicking adds a point. Enter closes the polygon, and backspace removes
+/// the last point.
+#[allow(clippy::too_many_arguments)]
+pub fn measure_on_click(
+    mut ctx: ResMut<EguiContext>,
+    mut click: Local<ClickDetector>,
+    tool: Res<Tool>,
+    mut measurement: ResMut<Measurement>,
+    mouse: Res<Input<MouseButton>>,
+    keys: Res<Input<KeyCode>>,
+    windows: Res<Windows>,
+    cursor: Res<CursorWorldspace>,
+    cameras: Query<&DatasetIdx, With<Camera>>,
+) {
+    let clicked = click.clicked(&mut ctx, &mouse, &windows);
+    if *tool != Tool::Measure {
+        return;
+    }
+    if clicked {
+        if let (Some(pt), Some(dataset)) = (cursor.pos, cursor.dataset(&cameras)) {
+            // Start over after a finished polygon, or in another dataset
+            if measurement.closed || measurement.dataset != dataset {
+                measurement.clear();
+                measurement.dataset = dataset;
+            }
+            measurement.points.push(pt);
+        }
+    }
+    if keys.just_pressed(KeyCode::Return) && measurement.points.len() >= 3 {
+        measurement.closed = true;
+    }
+    if keys.just_pressed(KeyCode::Back) {
+        measurement.closed = false;
+        measurement.points.pop();
+    }
+}
+
+pub fn draw_measurement(
+    measurement: Res<Measurement>,
+    mut commands: Commands,
+    query: Query<Entity, With<MeasurementShape>>,
+) {
+    if !measurement.is_changed() {
+        return;
+    }
+    for entity in &query {
+        commands.entity(entity).despawn();
+    }
+    if measurement.points.is_empty() {
+        return;
+    }
+
+    let mut builder = GeometryBuilder::new().add(&shapes::Polygon {
+        points: measurement.points.clone(),
+        closed: measurement.closed,
+    });
+    for pt in &measurement.points {
+        builder = builder.add(&shapes::Circle {
+            radius: 2.0,
+            center: *pt,
+        });
+    }
+    commands.spawn((
+        builder.build(
+            DrawMode::Stroke(StrokeMode::new(Color::hex("FFC20A").unwrap(), 1.5)),
+            // Above the flood and selection
+            Transform::from_translation(Vec3::new(0.0, 0.0, 3.0)),
+        ),
+        MeasurementShape,
+        measurement.dataset.layer(),
+    ));
+}
+
+pub fn controls(
+    mut ctx: ResMut<EguiContext>,
+    tool: Res<Tool>,
+    mut measurement: ResMut<Measurement>,
+    mut query: Query<(&mut Grid, &DatasetIdx), With<ActiveGrid>>,
+) {
+    if *tool != Tool::Measure {
+        return;
+    }
+    egui::Window::new("Measure").show(ctx.ctx_mut(), |ui| {
+        ui.label("Click to add points, enter to close a polygon, backspace to undo");
+        let lengths: Vec<f32> = measurement.segment_lengths().collect();
+        if let Some(last) = lengths.last() {
+            ui.label(format!("Last segment: {last:.1} m"));
+            ui.label(format!(
+                "Total, over {} segments: {:.1} m",
+                lengths.len(),
+                lengths.iter().sum::<f32>()
+            ));
+            if !measurement.closed {
+                let first = measurement.points[0];
+                let last = measurement.points[measurement.points.len() - 1];
+                ui.label(format!(
+                    "Straight line from the first point: {:.1} m",
+                    first.distance(last)
+                ));
+            }
+        }
+        if let Some(polygon) = measurement.polygon() {
+            ui.label(format!("Area: {:.0} m²", polygon.unsigned_area()));
+            let grid = query
+                .iter()
+                .find(|(_, idx)| **idx == measurement.dataset)
+                .map(|(grid, _)| grid);
+            match grid.and_then(|grid| grid.building_fraction(&polygon)) {
+                Some(fraction) => ui.label(format!("Buildings cover {:.1}%", 100.0 * fraction)),
+                None => ui.label("Too small to compare with the grid"),
+            };
+        }
+
+        ui.horizontal(|ui| {
+            if !measurement.closed
+                && measurement.points.len() >= 3
+                && ui.button("Close polygon").clicked()
+            {
+                measurement.closed = true;
+            }
+            if ui.button("Clear").clicked() {
+                measurement.clear();
+            }
+            if let Some(geometry) = measurement.geometry() {
+                if ui.button("Flood from here").clicked() {
+                    for (mut grid, idx) in &mut query {
+                        if *idx == measurement.dataset {
+                            let cells = grid.cells_on(&geometry, 0.0);
+                            grid.start_flood(cells, Some("a measured line".to_string()));
+                        }
+                    }
+                }
+            }
+        });
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn measurement(points: &[(f32, f32)], closed: bool) -> Measurement {
+        Measurement {
+            dataset: DatasetIdx(0),
+            points: points.iter().map(|(x, y)| Vec2::new(*x, *y)).collect(),
+            closed,
+        }
+    }
+
+    #[test]
+    fn open_lines_have_no_area() {
+        let line = measurement(&[(0.0, 0.0), (3.0, 4.0), (3.0, 10.0)], false);
+        assert_eq!(line.segment_lengths().collect::<Vec<_>>(), vec![5.0, 6.0]);
+        assert!(line.polygon().is_none());
+        assert!(matches!(line.geometry(), Some(Geometry::LineString(_))));
+    }
+
+    #[test]
+    fn closed_polygons_measure_the_closing_side_and_area() {
+        let square = measurement(&[(0.0, 0.0), (10.0, 0.0), (10.0, 10.0), (0.0, 10.0)], true);
+        assert_eq!(
+            square.segment_lengths().collect::<Vec<_>>(),
+            vec![10.0, 10.0, 10.0, 10.0]
+        );
+        assert_eq!(square.polygon().unwrap().unsigned_area(), 100.0);
+    }
+
+    #[test]
+    fn one_point_is_a_point() {
+        let point = measurement(&[(1.0, 2.0)], false);
+        assert_eq!(point.segment_lengths().count(), 0);
+        assert!(matches!(point.geometry(), Some(Geometry::Point(_))));
+        assert!(measurement(&[], false).geometry().is_none());
+    }
+}
@@ -0,0 +1,179 @@
+use bevy::core_pipeline::clear_color::ClearColorConfig;
+use bevy::prelude::{
+    default, Camera, Camera2d, Camera2dBundle, Color, Commands, Component, Entity, Input, KeyCode,
+    Local, MouseButton, OrthographicProjection, Query, Res, ResMut, Transform, UVec2, Vec2, Vec3,
+    Windows, With, Without,
+};
+use bevy::render::camera::Viewport;
+use bevy::render::view::RenderLayers;
+use bevy_egui::EguiContext;
+use bevy_pancam::PanCam;
+use bevy_prototype_lyon::prelude::{
+    DrawMode, FillMode, GeometryBuilder, Path, ShapePath, StrokeMode,
+};
+use bevy_prototype_lyon::shapes;
+
+use crate::camera;
+use crate::cursor_worldspace::{ClickDetector, CursorWorldspace};
+use crate::load_geo::Datasets;
+
+// Only the minimap camera sees things on this layer
+const MINIMAP_LAYER: u8 = RenderLayers::TOTAL_LAYERS as u8 - 1;
+// The longest side of the minimap, in logical pixels
+const MINIMAP_SIZE: f32 = 200.0;
+// Between the minimap and the corner of the window, in logical pixels
+const CORNER_GAP: f32 = 10.0;
+
+/// Marks the camera showing everything in the first dataset, in the bottom-right corner
+#[derive(Component)]
+pub struct Minimap {
+    /// In logical pixels
+    size: Vec2,
+}
+
+/// Marks the outline of what the main camera sees
+#[derive(Component)]
+pub struct ViewOutline;
+
+pub fn setup(mut commands: Commands, datasets: Res<Datasets>) {
+    let extent = camera::full_extent(&datasets);
+    let aspect = (extent.width() / extent.height()) as f32;
+    let size = if aspect > 1.0 {
+        Vec2::new(MINIMAP_SIZE, MINIMAP_SIZE / aspect)
+    } else {
+        Vec2::new(MINIMAP_SIZE * aspect, MINIMAP_SIZE)
+    };
+
+    let mut bundle = Camera2dBundle {
+        camera: Camera {
+            // Above every dataset's camera
+            priority: datasets.0.len() as isize,
+            ..default()
+        },
+        // Draw over the main view
+        camera_2d: Camera2d {
+            clear_color: ClearColorConfig::None,
+        },
+        ..default()
+    };
+    camera::zoom_to_extent(extent, size, &mut bundle.transform, &mut bundle.projection);
+    let pixel = bundle.projection.scale;
+    commands.spawn((
+        bundle,
+        Minimap { size },
+        RenderLayers::from_layers(&[0, MINIMAP_LAYER]),
+    ));
+
+    // Just behind the buildings, but not behind the camera's far plane
+    let center = extent.center();
+    let center = Vec2::new(center.x as f32, center.y as f32);
+    commands.spawn((
+        GeometryBuilder::build_as(
+            &shapes::Rectangle {
+                extents: pixel * size,
+                origin: shapes::RectangleOrigin::CustomCenter(center),
+            },
+            DrawMode::Fill(FillMode::color(Color::rgb(0.15, 0.15, 0.15))),
+            Transform::from_translation(Vec3::new(0.0, 0.0, -0.05)),
+        ),
+        RenderLayers::layer(MINIMAP_LAYER),
+    ));
+    commands.spawn((
+        GeometryBuilder::build_as(
+            &shapes::Rectangle::default(),
+            DrawMode::Stroke(StrokeMode::new(Color::WHITE, 2.0 * pixel)),
+            // Above everything else
+            Transform::from_translation(Vec3::new(0.0, 0.0, 10.0)),
+        ),
+        ViewOutline,
+        RenderLayers::layer(MINIMAP_LAYER),
+    ));
+}
+
+/// Keeps the minimap in the corner, and outlines what the main camera sees
+#[allow(clippy::type_complexity)]
+pub fn update(
+    windows: Res<Windows>,
+    mut last_view: Local<Option<(Vec2, Vec2)>>,
+    mut minimap: Query<(&mut Camera, &Minimap)>,
+    main: Query<(&Transform, &OrthographicProjection), (With<PanCam>, Without<Minimap>)>,
+    mut outline: Query<&mut Path, With<ViewOutline>>,
+) {
+    let window = if let Some(window) = windows.get_primary() {
+        window
+    } else {
+        return;
+    };
+    for (mut camera, minimap) in &mut minimap {
+        let size = (minimap.size * window.scale_factor() as f32).as_uvec2();
+        let gap = (CORNER_GAP * window.scale_factor() as f32) as u32;
+        let (width, height) = (window.physical_width(), window.physical_height());
+        if width < size.x + gap || height < size.y + gap {
+            continue;
+        }
+        camera::set_viewport(
+            &mut camera,
+            Some(Viewport {
+                physical_position: UVec2::new(width - size.x - gap, height - size.y - gap),
+                physical_size: size,
+                ..default()
+            }),
+        );
+    }
+
+    let (transform, projection) = if let Ok(main) = main.get_single() {
+        main
+    } else {
+        return;
+    };
+    let center = transform.translation.truncate()
+        + projection.scale
+            * Vec2::new(
+                (projection.left + projection.right) / 2.0,
+                (projection.bottom + projection.top) / 2.0,
+            );
+    let extents = projection.scale
+        * Vec2::new(
+            projection.right - projection.left,
+            projection.top - projection.bottom,
+        );
+    if *last_view == Some((center, extents)) {
+        return;
+    }
+    *last_view = Some((center, extents));
+    for mut path in &mut outline {
+        *path = ShapePath::build_as(&shapes::Rectangle {
+            extents,
+            origin: shapes::RectangleOrigin::CustomCenter(center),
+        });
+    }
+}
+
+/// Clicking the minimap moves the main camera there. M shows and hides it.
+#[allow(clippy::too_many_arguments)]
+pub fn controls(
+    mut ctx: ResMut<EguiContext>,
+    mut click: Local<ClickDetector>,
+    mouse: Res<Input<MouseButton>>,
+    keys: Res<Input<KeyCode>>,
+    windows: Res<Windows>,
+    cursor: Res<CursorWorldspace>,
+    mut minimap: Query<(Entity, &mut Camera), With<Minimap>>,
+    mut main: Query<&mut Transform, With<PanCam>>,
+) {
+    let clicked = click.clicked(&mut ctx, &mouse, &windows);
+    for (entity, mut camera) in &mut minimap {
+        if keys.just_pressed(KeyCode::M) {
+            camera.is_active = !camera.is_active;
+        }
+        if !clicked || cursor.camera != Some(entity) {
+            continue;
+        }
+        if let Some(pt) = cursor.pos {
+            for mut transform in &mut main {
+                transform.translation.x = pt.x;
+                transform.translation.y = pt.y;
+            }
+        }
+    }
+}
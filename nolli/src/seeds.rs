@@ -0,0 +1,129 @@
+use std::collections::BTreeSet;
+
+use anyhow::{Context, Result};
+use bevy::prelude::{Query, Res, ResMut, Resource, With};
+use bevy_egui::{egui, EguiContext};
+use geo::{Geometry, MapCoords};
+
+use crate::grid::Grid;
+use crate::load_geo::{self, DatasetIdx, Datasets};
+use crate::ActiveGrid;
+
+/// A GeoJSON file of places to flood from, like station entrances, streets, or parks. Floods start
+/// at points, and along lines and the outlines of polygons.
+#[derive(Resource)]
+pub struct SeedsFile {
+    pub path: String,
+    /// Features with the same value for this property are one source. Empty means no grouping.
+    pub group_by: String,
+    /// Loaded from `--seeds` before starting, to flood from once the grids exist
+    at_startup: Option<Vec<(Geometry, Option<String>)>>,
+    /// The result of the last load
+    status: String,
+}
+
+impl SeedsFile {
+    /// With a path, loads it right away, so a bad file stops the app before any window opens
+    pub fn new(path: Option<String>, group_by: Option<String>) -> Result<Self> {
+        let mut file = Self {
+            path: "seeds.geojson".to_string(),
+            group_by: group_by.unwrap_or_default(),
+            at_startup: None,
+            status: String::new(),
+        };
+        if let Some(path) = path {
+            file.path = path;
+            file.at_startup = Some(
+                file.load()
+                    .with_context(|| format!("Couldn't load seeds from {}", file.path))?,
+            );
+        }
+        Ok(file)
+    }
+
+    fn load(&self) -> Result<Vec<(Geometry, Option<String>)>> {
+        let group_by = (!self.group_by.is_empty()).then_some(self.group_by.as_str());
+        load_geo::load_geometries(&self.path, group_by)
+    }
+
+    /// Starts flooding from every feature, in every dataset. Describes what happened.
+    fn start_floods(
+        &self,
+        features: &[(Geometry, Option<String>)],
+        datasets: &Datasets,
+        grids: &mut Query<(&mut Grid, &DatasetIdx), With<ActiveGrid>>,
+    ) -> String {
+        // Only names that started a flood somewhere count as sources
+        let mut sources: BTreeSet<&String> = BTreeSet::new();
+        // Features that didn't start a flood in any dataset
+        let mut outside = 0;
+        for (geometry, name) in features {
+            let mut flooded = false;
+            for (mut grid, idx) in grids.iter_mut() {
+                let projection = datasets.0[idx.0].projection;
+                let cells = grid.cells_on(&geometry.map_coords(|c| projection.to_meters(c)), 0.0);
+                if !cells.is_empty() {
+                    grid.start_flood(cells, name.clone());
+                    flooded = true;
+                }
+            }
+            if !flooded {
+                outside += 1;
+            } else if let Some(name) = name {
+                sources.insert(name);
+            }
+        }
+
+        let mut status = format!("Flooding from {} features", features.len() - outside);
+        if !self.group_by.is_empty() {
+            status.push_str(&format!(" in {} sources", sources.len()));
+        }
+        if outside > 0 {
+            status.push_str(&format!(
+                ", skipping {outside} outside the grid or only on buildings"
+            ));
+        }
+        status
+    }
+}
+
+/// Floods from `--seeds`, if it was passed
+pub fn load_at_startup(
+    mut file: ResMut<SeedsFile>,
+    datasets: Res<Datasets>,
+    mut grids: Query<(&mut Grid, &DatasetIdx), With<ActiveGrid>>,
+) {
+    if let Some(features) = file.at_startup.take() {
+        let status = file.start_floods(&features, &datasets, &mut grids);
+        println!("{status}");
+        file.status = status;
+    }
+}
+
+pub fn controls(
+    mut ctx: ResMut<EguiContext>,
+    mut file: ResMut<SeedsFile>,
+    datasets: Res<Datasets>,
+    mut grids: Query<(&mut Grid, &DatasetIdx), With<ActiveGrid>>,
+) {
+    let file = &mut *file;
+    egui::Window::new("Seeds").show(ctx.ctx_mut(), |ui| {
+        ui.horizontal(|ui| {
+            ui.label("GeoJSON");
+            ui.text_edit_singleline(&mut file.path);
+        });
+        ui.horizontal(|ui| {
+            ui.label("Group by property");
+            ui.text_edit_singleline(&mut file.group_by);
+        });
+        if ui.button("Load seeds").clicked() {
+            file.status = match file.load() {
+                Ok(features) => file.start_floods(&features, &datasets, &mut grids),
+                Err(err) => err.to_string(),
+            };
+        }
+        if !file.status.is_empty() {
+            ui.label(&file.status);
+        }
+    });
+}
@@ -0,0 +1,158 @@
+use bevy::prelude::{
+    default, Assets, Camera, Color, ColorMaterial, Commands, Component, Entity, EventWriter, Input,
+    Local, Mesh, MouseButton, Query, Res, ResMut, Resource, Transform, Vec3, Windows, With,
+};
+use bevy::sprite::MaterialMesh2dBundle;
+use bevy_egui::{egui, EguiContext};
+use geo::{Area, Contains, EuclideanLength, Geometry, Point};
+
+use crate::cursor_worldspace::{ClickDetector, CursorWorldspace};
+use crate::draw::{self, BuildingsChanged};
+use crate::grid::Grid;
+use crate::load_geo::{self, DatasetIdx, Datasets};
+use crate::{ActiveGrid, Tool};
+
+/// The building whose properties are shown, and which dataset it's from
+#[derive(Resource, Default, PartialEq)]
+pub struct Selection(pub Option<(DatasetIdx, usize)>);
+
+/// Marks the outline drawn over the selected building
+#[derive(Component)]
+pub struct SelectionHighlight;
+
+/// Clicking a building selects it, and clicking nothing clears the selection. Clicks outside
+/// every dataset's view are ignored.
+#[allow(clippy::too_many_arguments)]
+pub fn select_on_click(
+    mut ctx: ResMut<EguiContext>,
+    mut click: Local<ClickDetector>,
+    tool: Res<Tool>,
+    mut selection: ResMut<Selection>,
+    mouse: Res<Input<MouseButton>>,
+    windows: Res<Windows>,
+    cursor: Res<CursorWorldspace>,
+    cameras: Query<&DatasetIdx, With<Camera>>,
+    datasets: Res<Datasets>,
+) {
+    if !click.clicked(&mut ctx, &mouse, &windows) || *tool != Tool::Select {
+        return;
+    }
+
+    let (pt, dataset) = match (cursor.pos, cursor.dataset(&cameras)) {
+        (Some(pt), Some(dataset)) => (Point::new(pt.x as f64, pt.y as f64), dataset),
+        _ => return,
+    };
+    let new_selection = Selection(
+        datasets.0[dataset.0]
+            .buildings
+            .iter()
+            .position(|b| b.polygon.contains(&pt))
+            .map(|idx| (dataset, idx)),
+    );
+    if new_selection != *selection {
+        *selection = new_selection;
+    }
+}
+
+pub fn highlight_selection(
+    selection: Res<Selection>,
+    datasets: Res<Datasets>,
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+    query: Query<Entity, With<SelectionHighlight>>,
+) {
+    // The selected building might've moved
+    if !selection.is_changed() && !datasets.is_changed() {
+        return;
+    }
+    for entity in &query {
+        commands.entity(entity).despawn();
+    }
+    if let Some((dataset, idx)) = selection.0 {
+        let polygon = datasets.0[dataset.0].buildings[idx].polygon.clone();
+        commands.spawn((
+            MaterialMesh2dBundle {
+                mesh: meshes.add(load_geo::polygons_to_mesh(vec![polygon])).into(),
+                material: materials.add(ColorMaterial::from(Color::hex("FFC20A").unwrap())),
+                // Above the flood
+                transform: Transform::from_translation(Vec3::new(0.0, 0.0, 2.0)),
+                ..default()
+            },
+            SelectionHighlight,
+            dataset.layer(),
+        ));
+    }
+}
+
+pub fn inspector(
+    mut ctx: ResMut<EguiContext>,
+    mut selection: ResMut<Selection>,
+    mut datasets: ResMut<Datasets>,
+    mut events: EventWriter<BuildingsChanged>,
+    mut grids: Query<(&mut Grid, &DatasetIdx), With<ActiveGrid>>,
+) {
+    let building = if let Some((dataset, idx)) = selection.0 {
+        &datasets.0[dataset.0].buildings[idx]
+    } else {
+        return;
+    };
+    let mut clear = false;
+    let mut delete = false;
+    let mut flood = false;
+    egui::Window::new("Selected building").show(ctx.ctx_mut(), |ui| {
+        let perimeter: f64 = std::iter::once(building.polygon.exterior())
+            .chain(building.polygon.interiors())
+            .map(|ring| ring.euclidean_length())
+            .sum();
+        ui.horizontal(|ui| {
+            ui.label(format!(
+                "{:.0} m² footprint, {perimeter:.0} m perimeter",
+                building.polygon.unsigned_area()
+            ));
+            clear = ui.button("Clear").clicked();
+            delete = ui.button("Delete (Del)").clicked();
+        });
+        flood = ui.button("Flood from the outline").clicked();
+
+        ui.separator();
+        if building.properties.is_empty() {
+            ui.label("No properties");
+        }
+        egui::ScrollArea::vertical()
+            .max_height(300.0)
+            .show(ui, |ui| {
+                egui::Grid::new("properties").striped(true).show(ui, |ui| {
+                    for (key, value) in &building.properties {
+                        ui.label(key);
+                        match value.as_str() {
+                            Some(x) => ui.label(x),
+                            None => ui.label(value.to_string()),
+                        };
+                        ui.end_row();
+                    }
+                });
+            });
+    });
+    if flood {
+        let dataset = selection.0.unwrap().0;
+        let outline = Geometry::Polygon(building.polygon.clone());
+        let source = match building.properties.get("name").and_then(|x| x.as_str()) {
+            Some(name) => format!("the outline of {name}"),
+            None => "a building's outline".to_string(),
+        };
+        for (mut grid, idx) in &mut grids {
+            if *idx == dataset {
+                // The outline itself is mostly building, so start from just outside it
+                let cells = grid.cells_on(&outline, grid.resolution_meters());
+                grid.start_flood(cells, Some(source.clone()));
+            }
+        }
+    }
+    if clear {
+        selection.0 = None;
+    }
+    if delete {
+        draw::delete_selected(&mut selection, &mut datasets, &mut events);
+    }
+}
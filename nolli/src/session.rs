@@ -0,0 +1,359 @@
+use anyhow::{bail, Result};
+use bevy::ecs::query::ReadOnlyWorldQuery;
+use bevy::prelude::{Query, Res, ResMut, Resource, Vec2, With, Without};
+use bevy_egui::{egui, EguiContext};
+use geo::Coord;
+use geojson::GeoJson;
+use serde::{Deserialize, Serialize};
+
+use crate::grid::{FloodSeed, Grid, GridParams};
+use crate::load_geo::{self, Dataset, DatasetIdx, Datasets};
+use crate::{ActiveGrid, FloodState, LoadedGrid, OriginalGrid};
+
+/// Everything needed to reproduce a flood: the inputs, the edits, and where and how far it spread
+#[derive(Resource, Serialize, Deserialize)]
+pub struct Session {
+    pub datasets: Vec<DatasetSession>,
+    pub paused: bool,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct DatasetSession {
+    /// The GeoJSON file originally loaded
+    pub path: String,
+    /// To notice if the file changed since
+    grid: GridParams,
+    /// Every building after drawing, moving, and deleting, in WGS84. Missing without those edits.
+    buildings: Option<GeoJson>,
+    /// Cells painted and erased, as (x, y) in the grid
+    painted: Vec<(usize, usize)>,
+    erased: Vec<(usize, usize)>,
+    seeds: Vec<Seed>,
+    /// How many steps the flood has spread
+    step: usize,
+}
+
+/// Where a flood started, and at which step
+#[derive(Serialize, Deserialize)]
+struct Seed {
+    /// The center of each cell it started from, as (longitude, latitude)
+    points: Vec<(f64, f64)>,
+    step: usize,
+    source: Option<String>,
+}
+
+/// Where to save sessions
+#[derive(Resource)]
+pub struct SessionFile {
+    pub path: String,
+    /// The result of the last save
+    status: String,
+}
+
+impl SessionFile {
+    pub fn new(path: String) -> Self {
+        Self {
+            path,
+            status: String::new(),
+        }
+    }
+}
+
+impl Session {
+    pub fn load(path: &str) -> Result<Self> {
+        Ok(serde_json::from_str(&std::fs::read_to_string(path)?)?)
+    }
+
+    /// Loads each input file, with any drawn, moved, or deleted buildings
+    pub fn load_datasets(&self) -> Result<Vec<Dataset>> {
+        let mut datasets: Vec<Dataset> = Vec::new();
+        for session in &self.datasets {
+            let mut dataset = load_geo::load_buildings(&session.path, datasets.first())?;
+            if let Some(ref buildings) = session.buildings {
+                dataset.replace_buildings(buildings.clone())?;
+            }
+            datasets.push(dataset);
+        }
+        Ok(datasets)
+    }
+}
+
+impl DatasetSession {
+    /// Describes one dataset's edits, compared to the grid first loaded, and its flood
+    fn new(dataset: &Dataset, active: &Grid, original: &Grid, loaded: &Grid) -> Self {
+        let params = original.params();
+        let mut painted = Vec::new();
+        let mut erased = Vec::new();
+        for y in 0..params.rows {
+            for x in 0..params.cols {
+                match (loaded.is_building(x, y), original.is_building(x, y)) {
+                    (false, true) => painted.push((x, y)),
+                    (true, false) => erased.push((x, y)),
+                    _ => {}
+                }
+            }
+        }
+        let seeds = active
+            .seeds()
+            .iter()
+            .map(|seed| Seed {
+                points: seed
+                    .cells
+                    .iter()
+                    .map(|(x, y)| {
+                        let pt = active.center_of_cell(*x, *y);
+                        let lonlat = dataset.projection.to_lonlat(pt.into());
+                        (lonlat.x, lonlat.y)
+                    })
+                    .collect(),
+                step: seed.step,
+                source: seed.source.clone(),
+            })
+            .collect();
+        Self {
+            path: dataset.path.clone(),
+            grid: params,
+            buildings: dataset.edited.then(|| dataset.to_geojson()),
+            painted,
+            erased,
+            seeds,
+            step: active.step(),
+        }
+    }
+
+    /// Applies the painting and erasing to the loaded grid, then returns it flooded from the
+    /// same seeds for the same number of steps. Nothing happens if the grid doesn't match the
+    /// saved one.
+    fn restore(&self, dataset: &Dataset, original: &mut Grid) -> Option<Grid> {
+        if original.params() != self.grid {
+            return None;
+        }
+        for (x, y) in &self.painted {
+            original.set_building(*x, *y, true);
+        }
+        for (x, y) in &self.erased {
+            original.set_building(*x, *y, false);
+        }
+
+        let seeds: Vec<FloodSeed> = self
+            .seeds
+            .iter()
+            .map(|seed| FloodSeed {
+                cells: seed
+                    .points
+                    .iter()
+                    .filter_map(|(lon, lat)| {
+                        let pt = dataset.projection.to_meters(Coord { x: *lon, y: *lat });
+                        original.world_to_cell(Vec2::new(pt.x as f32, pt.y as f32))
+                    })
+                    .collect(),
+                step: seed.step,
+                source: seed.source.clone(),
+            })
+            .collect();
+        let mut grid = original.clone();
+        grid.replay_flood(&seeds, self.step);
+        Some(grid)
+    }
+}
+
+/// Describes every dataset's edits and flood
+pub fn save(
+    path: &str,
+    datasets: &Datasets,
+    paused: bool,
+    active: &Query<(&Grid, &DatasetIdx), With<ActiveGrid>>,
+    original: &Query<(&Grid, &DatasetIdx), With<OriginalGrid>>,
+    loaded: &Query<(&Grid, &DatasetIdx), With<LoadedGrid>>,
+) -> Result<()> {
+    let mut sessions = Vec::new();
+    for (idx, dataset) in datasets.0.iter().enumerate() {
+        match (
+            find(active, DatasetIdx(idx)),
+            find(original, DatasetIdx(idx)),
+            find(loaded, DatasetIdx(idx)),
+        ) {
+            (Some(active), Some(original), Some(loaded)) => {
+                sessions.push(DatasetSession::new(dataset, active, original, loaded));
+            }
+            _ => bail!("No grid for {}", dataset.path),
+        }
+    }
+
+    let session = Session {
+        datasets: sessions,
+        paused,
+    };
+    std::fs::write(path, serde_json::to_string_pretty(&session)?)?;
+    Ok(())
+}
+
+fn find<'a, F: ReadOnlyWorldQuery>(
+    query: &'a Query<(&Grid, &DatasetIdx), F>,
+    idx: DatasetIdx,
+) -> Option<&'a Grid> {
+    query.iter().find(|(_, i)| **i == idx).map(|(grid, _)| grid)
+}
+
+/// Once the grids exist, applies the session's painting and erasing, then floods from the same
+/// seeds for the same number of steps
+#[allow(clippy::type_complexity)]
+pub fn restore(
+    session: Option<Res<Session>>,
+    datasets: Res<Datasets>,
+    mut state: ResMut<FloodState>,
+    mut active: Query<(&mut Grid, &DatasetIdx), With<ActiveGrid>>,
+    mut original: Query<(&mut Grid, &DatasetIdx), (With<OriginalGrid>, Without<ActiveGrid>)>,
+) {
+    let session = if let Some(session) = session {
+        session
+    } else {
+        return;
+    };
+    state.paused = session.paused;
+    for (idx, dataset_session) in session.datasets.iter().enumerate() {
+        let dataset = &datasets.0[idx];
+        let (mut original, _) = original
+            .iter_mut()
+            .find(|(_, i)| **i == DatasetIdx(idx))
+            .unwrap();
+        let restored = match dataset_session.restore(dataset, &mut original) {
+            Some(grid) => grid,
+            None => {
+                // Still show the file, without anything that might be in the wrong place
+                println!(
+                    "{} has changed since the session was saved, so its edits and flood are \
+                     skipped",
+                    dataset.path
+                );
+                continue;
+            }
+        };
+        for (mut grid, i) in &mut active {
+            if *i == DatasetIdx(idx) {
+                *grid = restored.clone();
+            }
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn controls(
+    mut ctx: ResMut<EguiContext>,
+    mut file: ResMut<SessionFile>,
+    datasets: Res<Datasets>,
+    state: Res<FloodState>,
+    active: Query<(&Grid, &DatasetIdx), With<ActiveGrid>>,
+    original: Query<(&Grid, &DatasetIdx), With<OriginalGrid>>,
+    loaded: Query<(&Grid, &DatasetIdx), With<LoadedGrid>>,
+) {
+    let file = &mut *file;
+    egui::Window::new("Session").show(ctx.ctx_mut(), |ui| {
+        ui.horizontal(|ui| {
+            ui.text_edit_singleline(&mut file.path);
+            if ui.button("Save").clicked() {
+                file.status = match save(
+                    &file.path,
+                    &datasets,
+                    state.paused,
+                    &active,
+                    &original,
+                    &loaded,
+                ) {
+                    Ok(()) => format!("Saved {}; open it with --session={}", file.path, file.path),
+                    Err(err) => err.to_string(),
+                };
+            }
+        });
+        if !file.status.is_empty() {
+            ui.label(&file.status);
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use common::projection::Projection;
+    use geo::Rect;
+
+    use super::*;
+    use crate::load_geo::Building;
+
+    fn dataset() -> Dataset {
+        let bbox = Rect::new((0.0, 0.0), (100.0, 100.0));
+        Dataset {
+            path: "rome.geojson".to_string(),
+            buildings: vec![Building {
+                polygon: Rect::new((30.0, 30.0), (50.0, 70.0)).to_polygon(),
+                properties: Default::default(),
+                feature: Some(0),
+                id: None,
+            }],
+            bbox,
+            projection: Projection::new(Coord { x: 12.48, y: 41.89 }),
+            other_features: Vec::new(),
+            edited: false,
+        }
+    }
+
+    fn loaded_grid(dataset: &Dataset) -> Grid {
+        let polygons: Vec<_> = dataset
+            .buildings
+            .iter()
+            .map(|b| b.polygon.clone())
+            .collect();
+        Grid::from_polygons(&polygons, dataset.bbox)
+    }
+
+    #[test]
+    fn save_then_restore_reproduces_the_flood() {
+        let dataset = dataset();
+        let loaded = loaded_grid(&dataset);
+        let mut original = loaded.clone();
+        original.set_building(7, 7, true);
+        original.set_building(4, 4, false);
+        let mut active = original.clone();
+        active.start_flood(vec![(0, 0)], None);
+        for _ in 0..3 {
+            active.flood();
+        }
+        active.start_flood(vec![(9, 0), (9, 1)], Some("station".to_string()));
+        for _ in 0..4 {
+            active.flood();
+        }
+
+        let session = DatasetSession::new(&dataset, &active, &original, &loaded);
+        let json = serde_json::to_string(&session).unwrap();
+        let session: DatasetSession = serde_json::from_str(&json).unwrap();
+        let mut restored_original = loaded.clone();
+        let restored = session.restore(&dataset, &mut restored_original).unwrap();
+
+        assert_eq!(restored.step(), active.step());
+        let params = active.params();
+        for y in 0..params.rows {
+            for x in 0..params.cols {
+                assert_eq!(
+                    restored_original.is_building(x, y),
+                    original.is_building(x, y)
+                );
+                assert_eq!(restored.arrival_step(x, y), active.arrival_step(x, y));
+            }
+        }
+        assert_eq!(restored.seeds().len(), active.seeds().len());
+        for (a, b) in restored.seeds().iter().zip(active.seeds()) {
+            assert_eq!(a.cells, b.cells);
+            assert_eq!(a.step, b.step);
+            assert_eq!(a.source, b.source);
+        }
+    }
+
+    #[test]
+    fn restoring_onto_a_different_grid_does_nothing() {
+        let dataset = dataset();
+        let loaded = loaded_grid(&dataset);
+        let session = DatasetSession::new(&dataset, &loaded, &loaded, &loaded);
+
+        let mut other = Grid::from_polygons(&[], Rect::new((0.0, 0.0), (200.0, 100.0)));
+        assert!(session.restore(&dataset, &mut other).is_none());
+    }
+}